@@ -2,13 +2,56 @@
 
 pub const ICON_SIZE: usize = 100;
 
+/// Most application channels whose levels fit into [`HostMessage::UpdatePeaks`].
+pub const MAX_PEAK_CHANNELS: usize = 8;
+
+/// Levels of the application channels, in channel order.
+pub type Peaks = heapless::Vec<u8, MAX_PEAK_CHANNELS>;
+
+/// Quantize a meter level (`0.0` to `1.0`) for [`HostMessage::UpdatePeaks`].
+pub fn quantize_level(level: f32) -> u8 {
+    // no `f32::round()` without std, adding 0.5 before truncating does the same here
+    (level.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+/// Turn a quantized level back into a meter level (`0.0` to `1.0`).
+pub fn level_from_quantized(level: u8) -> f32 {
+    f32::from(level) / 255.0
+}
+
+/// Most characters of a channel label, as many as fit below an icon.
+pub const LABEL_LEN: usize = 16;
+
+/// Text shown below a channel's icon, printable ASCII only.
+pub type Label = heapless::String<LABEL_LEN>;
+
+/// Shorten `text` to a [`Label`], replacing characters the device cannot show with `?`.
+pub fn make_label(text: &str) -> Label {
+    text.chars()
+        .take(LABEL_LEN)
+        .map(|c| {
+            if c == ' ' || c.is_ascii_graphic() {
+                c
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
+/// Version of the protocol spoken with [`HostMessage`] and [`DeviceMessage`].
+///
+/// Bump this whenever the messages change incompatibly.  The host and the device tell each other
+/// their version in the `Hello` messages, which therefore have to stay the first variants.
+pub const PROTOCOL_VERSION: u16 = 7;
+
+/// A channel of the mixer.
+///
+/// Application channels are numbered from 0, left to right.  How many of them exist depends on
+/// the device, which reports it in its [`DeviceInfo`].
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
-#[repr(u8)]
 pub enum Channel {
-    Ch1,
-    Ch2,
-    Ch3,
-    Ch4,
+    App(u8),
     Main,
 }
 
@@ -16,22 +59,16 @@ impl Channel {
     #[inline]
     pub fn to_index(self) -> usize {
         match self {
-            Channel::Ch1 => 0,
-            Channel::Ch2 => 1,
-            Channel::Ch3 => 2,
-            Channel::Ch4 => 3,
+            Channel::App(i) => i as usize,
             Channel::Main => panic!("called to_index() for Channel::Main"),
         }
     }
 
     #[inline]
     pub fn from_index(i: usize) -> Self {
-        match i {
-            0 => Channel::Ch1,
-            1 => Channel::Ch2,
-            2 => Channel::Ch3,
-            3 => Channel::Ch4,
-            i => panic!("invalid channel index {}", i),
+        match u8::try_from(i) {
+            Ok(i) => Channel::App(i),
+            Err(_) => panic!("invalid channel index {}", i),
         }
     }
 }
@@ -51,16 +88,358 @@ impl ChannelState {
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone, Copy)]
+/// What the device reports about itself in the handshake.
+///
+/// Fields may only be added at the end so older versions can still read the protocol version.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct DeviceInfo {
+    pub protocol_version: u16,
+    /// Version of the firmware as `(major, minor, patch)`.
+    pub firmware_version: (u8, u8, u8),
+    /// Number of application channels.
+    pub channel_count: u8,
+    pub capabilities: Capabilities,
+}
+
+/// Optional features of the device.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Capabilities {
+    /// The device has a display.
+    pub display: bool,
+    /// The display can show channel icons sent as [`IconHeader`] frames.
+    pub icons: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
 pub enum HostMessage {
-    UpdatePeak(Channel, f32),
+    /// Start of the handshake with the host's protocol version, answered by
+    /// [`DeviceMessage::Hello`].
+    Hello(u16),
+    /// Meter levels of the main channel and the application channels, see [`quantize_level`].
+    UpdatePeaks(u8, Peaks),
     UpdateChannelState(Channel, ChannelState),
-    SetIcon(Channel),
     ForceUpdate,
+    /// Light the sync LED while the fader does not control the channel's volume yet.
+    SetSyncLed(Channel, bool),
+    /// Blink the channel's button LED to confirm an action.
+    Blink(Channel),
+    /// Move the peak-hold marker of the channel's level meter (`0.0` to `1.0`).
+    SetPeakHold(Channel, f32),
+    /// Show a label below the channel's icon, which is cleared along with the icon.
+    SetLabel(Channel, Label),
+    /// Whether the channel's button reports [`ButtonEvent::DoublePress`].  Without, clicks are
+    /// reported right away instead of waiting for a second press.
+    SetDoublePress(Channel, bool),
+}
+
+impl HostMessage {
+    /// The channel this message is addressed to, if any.
+    pub fn channel(&self) -> Option<Channel> {
+        match *self {
+            HostMessage::UpdateChannelState(ch, _)
+            | HostMessage::SetSyncLed(ch, _)
+            | HostMessage::Blink(ch)
+            | HostMessage::SetPeakHold(ch, _)
+            | HostMessage::SetLabel(ch, _)
+            | HostMessage::SetDoublePress(ch, _) => Some(ch),
+            HostMessage::Hello(_) | HostMessage::UpdatePeaks(_, _) | HostMessage::ForceUpdate => {
+                None
+            }
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone, Copy)]
 pub enum DeviceMessage {
+    Hello(DeviceInfo),
     UpdateVolume(Channel, f32),
-    ToggleChannelMute(Channel),
+    /// Something happened with the channel's mute button.
+    Button(Channel, ButtonEvent),
+    /// Outcome of the icon transfer with the given sequence number.
+    IconStatus(u16, IconStatus),
+}
+
+/// What a mute button reports, after debouncing.
+///
+/// Every press is reported as [`Pressed`][ButtonEvent::Pressed] and
+/// [`Released`][ButtonEvent::Released], followed by at most one of the gestures.  A click which
+/// turned out not to start a double press can be reported after the next press.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    /// Pressed and released once, without a second press following quickly.
+    Click,
+    /// Pressed and released twice in quick succession.
+    DoublePress,
+    /// Held down for a while, reported before the button is released.
+    LongPress,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IconStatus {
+    /// The icon was received intact and is shown now.
+    Shown,
+    /// The image data did not match the checksum from the header.
+    BadChecksum,
+    /// The image does not fit the display or channel, or could not be decoded.
+    Invalid,
+    /// A new frame started before all image data was received.
+    Incomplete,
+}
+
+/// Size of a decoded icon in bytes, as big-endian RGB565 pixels.
+pub const ICON_BYTES: usize = ICON_SIZE * ICON_SIZE * 2;
+
+/// Most pixels a single run or literal of an encoded icon can hold.
+pub const ICON_RUN_MAX: usize = 128;
+
+/// Header in front of each icon sent over the bulk endpoint.
+///
+/// The header always starts a USB packet, which lets the device find the start of the next frame
+/// after a transfer got lost.  It is followed by `length` bytes of image data, encoded as
+/// described for the [`IconDecoder`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct IconHeader {
+    pub channel: Channel,
+    /// Chosen by the host to match the [`DeviceMessage::IconStatus`] to the transfer.
+    pub sequence: u16,
+    pub length: u32,
+    /// [`Crc32`] of the encoded image data.
+    pub crc: u32,
+}
+
+impl IconHeader {
+    pub const SIZE: usize = 16;
+    const MAGIC: [u8; 4] = *b"PMIC";
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0x00; Self::SIZE];
+        buf[0..4].copy_from_slice(&Self::MAGIC);
+        buf[4] = match self.channel {
+            Channel::App(i) => i,
+            Channel::Main => u8::MAX,
+        };
+        buf[6..8].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.length.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        buf
+    }
+
+    /// Parse the header at the start of `buf`, if there is one.
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..Self::SIZE)?;
+        if buf[0..4] != Self::MAGIC {
+            return None;
+        }
+        let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        Some(Self {
+            channel: match buf[4] {
+                u8::MAX => Channel::Main,
+                i => Channel::App(i),
+            },
+            sequence: u16::from_le_bytes([buf[6], buf[7]]),
+            length: u32_at(8),
+            crc: u32_at(12),
+        })
+    }
+}
+
+/// CRC-32 (as used by Ethernet and zlib), computed incrementally.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(u32::MAX)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= u32::from(byte);
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finish()
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DecoderState {
+    Tag,
+    /// Waiting for the pixel to repeat, possibly with its first byte received already.
+    Run {
+        count: usize,
+        high: Option<u8>,
+    },
+    Literal {
+        bytes_left: usize,
+    },
+}
+
+/// Streaming decoder for run-length encoded icons.
+///
+/// An encoded icon is a sequence of chunks, each starting with a tag byte:
+///
+/// - `0x00..=0x7f`: the following pixel repeats `tag + 1` times.
+/// - `0x80..=0xff`: `tag - 0x7f` pixels follow verbatim.
+///
+/// Pixels are two bytes each, as in the decoded image.  Chunks may be split across packets in
+/// any way, the decoder writes pixels into the output as soon as they are complete.
+#[derive(Debug, Clone, Copy)]
+pub struct IconDecoder {
+    state: DecoderState,
+    cursor: usize,
+}
+
+/// The encoded data describes more pixels than fit the output.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DecodeOverflow;
+
+impl IconDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: DecoderState::Tag,
+            cursor: 0,
+        }
+    }
+
+    /// Decode the next piece of encoded data into `out`, which must be the same for all calls.
+    pub fn push(&mut self, data: &[u8], out: &mut [u8]) -> Result<(), DecodeOverflow> {
+        for &byte in data {
+            self.state = match self.state {
+                DecoderState::Tag if byte < 0x80 => DecoderState::Run {
+                    count: usize::from(byte) + 1,
+                    high: None,
+                },
+                DecoderState::Tag => DecoderState::Literal {
+                    bytes_left: (usize::from(byte) - 0x7f) * 2,
+                },
+                DecoderState::Run { count, high: None } => DecoderState::Run {
+                    count,
+                    high: Some(byte),
+                },
+                DecoderState::Run {
+                    count,
+                    high: Some(high),
+                } => {
+                    let run = out
+                        .get_mut(self.cursor..self.cursor + count * 2)
+                        .ok_or(DecodeOverflow)?;
+                    for pixel in run.chunks_exact_mut(2) {
+                        pixel.copy_from_slice(&[high, byte]);
+                    }
+                    self.cursor += count * 2;
+                    DecoderState::Tag
+                }
+                DecoderState::Literal { bytes_left } => {
+                    *out.get_mut(self.cursor).ok_or(DecodeOverflow)? = byte;
+                    self.cursor += 1;
+                    match bytes_left - 1 {
+                        0 => DecoderState::Tag,
+                        bytes_left => DecoderState::Literal { bytes_left },
+                    }
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Whether the data so far decoded to exactly `len` bytes.
+    pub fn is_complete(&self, len: usize) -> bool {
+        matches!(self.state, DecoderState::Tag) && self.cursor == len
+    }
+}
+
+impl Default for IconDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icon_header_roundtrip_and_crc() {
+        let header = IconHeader {
+            channel: Channel::App(2),
+            sequence: 0x1234,
+            length: ICON_BYTES as u32,
+            crc: Crc32::checksum(b"123456789"),
+        };
+        // the standard check value of CRC-32
+        assert_eq!(header.crc, 0xcbf4_3926);
+
+        let mut packet = [0xaa; 64];
+        packet[..IconHeader::SIZE].copy_from_slice(&header.to_bytes());
+        assert_eq!(IconHeader::from_bytes(&packet), Some(header));
+        assert_eq!(IconHeader::from_bytes(&packet[1..]), None);
+        assert_eq!(IconHeader::from_bytes(&packet[..8]), None);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), header.crc);
+    }
+
+    #[test]
+    fn levels_survive_quantization() {
+        assert_eq!(quantize_level(-1.0), 0);
+        assert_eq!(quantize_level(2.0), 255);
+        for q in 0..=255 {
+            assert_eq!(quantize_level(level_from_quantized(q)), q);
+        }
+    }
+
+    #[test]
+    fn labels_are_shortened_to_ascii() {
+        assert_eq!(make_label("Firefox"), "Firefox");
+        assert_eq!(make_label("Mpv – Élan"), "Mpv ? ?lan");
+        assert_eq!(
+            make_label("A very long application name"),
+            "A very long appl"
+        );
+    }
+
+    #[test]
+    fn icon_decoder_handles_split_chunks() {
+        // three pixels 0x1234, then 0xabcd and 0x0001 verbatim
+        let encoded = [0x02, 0x12, 0x34, 0x81, 0xab, 0xcd, 0x00, 0x01];
+        let expected = [0x12, 0x34, 0x12, 0x34, 0x12, 0x34, 0xab, 0xcd, 0x00, 0x01];
+
+        for split in 0..encoded.len() {
+            let mut out = [0x00; 10];
+            let mut decoder = IconDecoder::new();
+            decoder.push(&encoded[..split], &mut out).unwrap();
+            assert!(!decoder.is_complete(out.len()));
+            decoder.push(&encoded[split..], &mut out).unwrap();
+            assert!(decoder.is_complete(out.len()));
+            assert_eq!(out, expected);
+        }
+
+        let mut out = [0x00; 4];
+        assert_eq!(
+            IconDecoder::new().push(&encoded, &mut out),
+            Err(DecodeOverflow)
+        );
+    }
 }
@@ -1,8 +1,18 @@
+use crate::font;
 use embedded_hal::digital::v2::OutputPin;
 
+/// Height of the label area below each icon.
+const LABEL_ROWS: usize = font::GLYPH_HEIGHT + 1;
+/// Width of a character including the spacing to the next one.
+const LABEL_CELL: usize = font::GLYPH_WIDTH + 1;
+const LABEL_COLOR: u16 = 0xffff;
+
 struct ActiveIconStream {
-    ch: common::Channel,
-    cursor: usize,
+    header: common::IconHeader,
+    /// Encoded bytes received so far.
+    received: usize,
+    crc: common::Crc32,
+    decoder: common::IconDecoder,
 }
 
 pub struct Gui<SPI, CS, DC, RST, BL> {
@@ -25,8 +35,7 @@ where
         backlight: BL,
     ) -> Self {
         let icon_buf = cortex_m::singleton!(
-            :[u8; common::ICON_SIZE * common::ICON_SIZE * 2]
-                = [0x00; common::ICON_SIZE * common::ICON_SIZE * 2]
+            :[u8; common::ICON_BYTES] = [0x00; common::ICON_BYTES]
         )
         .unwrap();
         Self {
@@ -46,12 +55,16 @@ where
         let _ = self.backlight.set_high();
     }
 
+    fn has_icon(ch: common::Channel) -> bool {
+        matches!(ch, common::Channel::App(0..=3))
+    }
+
     fn icon_coords(ch: common::Channel) -> (u16, u16, u16, u16) {
         let (x, y) = match ch {
-            common::Channel::Ch1 => (10, 10),
-            common::Channel::Ch2 => (10, 130),
-            common::Channel::Ch3 => (130, 10),
-            common::Channel::Ch4 => (130, 130),
+            common::Channel::App(0) => (10, 10),
+            common::Channel::App(1) => (10, 130),
+            common::Channel::App(2) => (130, 10),
+            common::Channel::App(3) => (130, 130),
             _ => unreachable!(),
         };
         (
@@ -62,6 +75,30 @@ where
         )
     }
 
+    /// Draw a line of text centered below the channel's icon, replacing the previous label.
+    pub fn set_label(&mut self, ch: common::Channel, label: &str) {
+        if !Self::has_icon(ch) {
+            return;
+        }
+        let mut buf = [0x00; common::ICON_SIZE * 2 * LABEL_ROWS];
+        let len = label.len().min(common::ICON_SIZE / LABEL_CELL);
+        let left = (common::ICON_SIZE - len * LABEL_CELL) / 2;
+        for (i, c) in label.bytes().take(len).enumerate() {
+            for (col, bits) in font::glyph(c).iter().enumerate() {
+                let x = left + i * LABEL_CELL + col;
+                for row in (0..font::GLYPH_HEIGHT).filter(|row| bits & (1 << row) != 0) {
+                    let offset = (row * common::ICON_SIZE + x) * 2;
+                    buf[offset..offset + 2].copy_from_slice(&LABEL_COLOR.to_be_bytes());
+                }
+            }
+        }
+
+        let (x1, _, x2, y2) = Self::icon_coords(ch);
+        let _ = self
+            .display
+            .write_fb_partial(x1, y2 + 2, x2, y2 + 1 + LABEL_ROWS as u16, &buf);
+    }
+
     pub fn clear_icon(&mut self, ch: common::Channel) {
         const CLEARROWS: u16 = 4;
         let (x, y, _, _) = Self::icon_coords(ch);
@@ -75,27 +112,62 @@ where
                 &clearbuf[..],
             );
         }
+        self.set_label(ch, "");
     }
 
-    pub fn start_icon_stream(&mut self, ch: common::Channel) {
-        self.active_icon_stream = Some(ActiveIconStream { ch, cursor: 0 });
-    }
-
-    /// Call the closure to retrieve new icon data in case a stream is currently active.
-    pub fn try_push_icon_data_if_active<F>(&mut self, f: F)
+    /// Feed a packet received on the bulk endpoint into the icon transfer.
+    ///
+    /// `report` is called with the sequence number and outcome of each transfer which ended.
+    /// Packets which neither start a frame nor belong to one are dropped, so a lost transfer
+    /// only costs the icon it carried.
+    pub fn push_icon_packet<F>(&mut self, packet: &[u8], mut report: F)
     where
-        F: FnOnce(&mut [u8]) -> usize,
+        F: FnMut(u16, common::IconStatus),
     {
+        let mut data = packet;
+        if let Some(header) = common::IconHeader::from_bytes(packet) {
+            if let Some(info) = self.active_icon_stream.take() {
+                report(info.header.sequence, common::IconStatus::Incomplete);
+            }
+            if !Self::has_icon(header.channel) {
+                report(header.sequence, common::IconStatus::Invalid);
+                return;
+            }
+            self.active_icon_stream = Some(ActiveIconStream {
+                header,
+                received: 0,
+                crc: common::Crc32::new(),
+                decoder: common::IconDecoder::new(),
+            });
+            data = &packet[common::IconHeader::SIZE..];
+        }
+
         if let Some(info) = &mut self.active_icon_stream {
-            let new_bytes = f(&mut self.icon_buf[info.cursor..]);
-            info.cursor += new_bytes;
+            let len = data.len().min(info.header.length as usize - info.received);
+            let data = &data[..len];
+            info.crc.update(data);
+            info.received += len;
+            // Pixels are decoded straight into the icon buffer as they arrive.
+            if info.decoder.push(data, self.icon_buf).is_err() {
+                report(info.header.sequence, common::IconStatus::Invalid);
+                self.active_icon_stream = None;
+                return;
+            }
 
-            // Full icon received - write it to the display.
-            if info.cursor >= self.icon_buf.len() {
-                let (x1, y1, x2, y2) = Self::icon_coords(info.ch);
-                let _ = self
-                    .display
-                    .write_fb_partial(x1, y1, x2, y2, &self.icon_buf);
+            // Full icon received - write it to the display if it arrived intact.
+            if info.received >= info.header.length as usize {
+                let status = if info.crc.finish() != info.header.crc {
+                    common::IconStatus::BadChecksum
+                } else if !info.decoder.is_complete(self.icon_buf.len()) {
+                    common::IconStatus::Invalid
+                } else {
+                    let (x1, y1, x2, y2) = Self::icon_coords(info.header.channel);
+                    let _ = self
+                        .display
+                        .write_fb_partial(x1, y1, x2, y2, &self.icon_buf);
+                    common::IconStatus::Shown
+                };
+                report(info.header.sequence, status);
                 self.active_icon_stream = None;
             }
         }
@@ -36,19 +36,19 @@ pub async fn faders_task(
         cassette::yield_now().await;
 
         let ch1_value = adc1.read(&mut fader_ch1_adc).expect("Error reading ADC.");
-        enqueue_if_changed(common::Channel::Ch1, ch1_value, &mut previous_values[1]);
+        enqueue_if_changed(common::Channel::App(0), ch1_value, &mut previous_values[1]);
         cassette::yield_now().await;
 
         let ch2_value = adc1.read(&mut fader_ch2_adc).expect("Error reading ADC.");
-        enqueue_if_changed(common::Channel::Ch2, ch2_value, &mut previous_values[2]);
+        enqueue_if_changed(common::Channel::App(1), ch2_value, &mut previous_values[2]);
         cassette::yield_now().await;
 
         let ch3_value = adc1.read(&mut fader_ch3_adc).expect("Error reading ADC.");
-        enqueue_if_changed(common::Channel::Ch3, ch3_value, &mut previous_values[3]);
+        enqueue_if_changed(common::Channel::App(2), ch3_value, &mut previous_values[3]);
         cassette::yield_now().await;
 
         let ch4_value = adc1.read(&mut fader_ch4_adc).expect("Error reading ADC.");
-        enqueue_if_changed(common::Channel::Ch4, ch4_value, &mut previous_values[4]);
+        enqueue_if_changed(common::Channel::App(3), ch4_value, &mut previous_values[4]);
         cassette::yield_now().await;
 
         if pending_forced_update.get() {
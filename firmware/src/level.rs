@@ -13,12 +13,18 @@ where
     DCK: embedded_hal::digital::v2::OutputPin,
     SCK: embedded_hal::digital::v2::OutputPin,
 {
-    #[allow(unused_must_use)]
     pub fn update_level(&mut self, level: f32) {
+        self.update_level_with_hold(level, 0.0);
+    }
+
+    /// Show a level with the segment at `hold` lit as a peak-hold marker.
+    #[allow(unused_must_use)]
+    pub fn update_level_with_hold(&mut self, level: f32, hold: f32) {
         let value = (level * 20.5) as u32;
+        let hold = (hold * 20.5) as u32;
 
         for i in 0..20 {
-            if (19 - i) < value {
+            if (19 - i) < value || (19 - i) + 1 == hold {
                 self.data_pin.set_low();
             } else {
                 self.data_pin.set_high();
@@ -10,11 +10,48 @@ use core::cell::{Cell, RefCell};
 
 mod display;
 mod faders;
+mod font;
 mod level;
 mod mute;
 mod status_leds;
 mod usb;
 
+/// Number of application channels, reported to the host.
+pub const CHANNEL_COUNT: u8 = 4;
+
+/// What the firmware reports about itself in the handshake with the host.
+pub fn device_info() -> common::DeviceInfo {
+    let version = |part: &str| part.parse().unwrap_or(0);
+    common::DeviceInfo {
+        protocol_version: common::PROTOCOL_VERSION,
+        firmware_version: (
+            version(env!("CARGO_PKG_VERSION_MAJOR")),
+            version(env!("CARGO_PKG_VERSION_MINOR")),
+            version(env!("CARGO_PKG_VERSION_PATCH")),
+        ),
+        channel_count: CHANNEL_COUNT,
+        capabilities: common::Capabilities {
+            display: true,
+            icons: true,
+        },
+    }
+}
+
+/// Core clock frequency, must match `.sysclk()` in `main()`.
+const SYSCLK_HZ: u32 = 48_000_000;
+
+/// Current value of the cycle counter, for measuring time with [`millis_since()`].
+pub fn cycles() -> u32 {
+    cortex_m::peripheral::DWT::cycle_count()
+}
+
+/// Milliseconds passed since `start` (a value of [`cycles()`]).
+///
+/// The cycle counter wraps after about 89 seconds, so only shorter durations can be measured.
+pub fn millis_since(start: u32) -> u32 {
+    cycles().wrapping_sub(start) / (SYSCLK_HZ / 1000)
+}
+
 trait ResultWarn {
     fn err_warn(self, msg: &str);
 }
@@ -46,7 +83,7 @@ fn main() -> ! {
     rtt_target::rtt_init_print!();
 
     let dp = pac::Peripherals::take().unwrap();
-    let cp = cortex_m::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
 
     /*
      * Clocks
@@ -65,6 +102,9 @@ fn main() -> ! {
 
     assert!(clocks.usbclk_valid());
 
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
     let mut delay = stm32f3xx_hal::delay::Delay::new(cp.SYST, clocks);
 
     rprintln!("Hello World!");
@@ -373,8 +413,12 @@ fn main() -> ! {
 
     let pending_volume_updates =
         RefCell::new(heapless::LinearMap::<common::Channel, f32, 5>::new());
-    let pending_presses = RefCell::new(heapless::LinearMap::<common::Channel, (), 5>::new());
+    let pending_buttons = RefCell::new(mute::PendingButtons::new());
+    let double_press = Cell::new(mute::DoublePress::default());
     let pending_forced_update = Cell::new(false);
+    let pending_hello = Cell::new(false);
+    let pending_icon_status =
+        RefCell::new(heapless::LinearMap::<u16, common::IconStatus, 4>::new());
 
     rprintln!("Ready.");
     rprintln!("");
@@ -397,10 +441,19 @@ fn main() -> ! {
         status_leds_ch4,
         gui,
         &pending_forced_update,
+        &pending_hello,
+        &pending_icon_status,
+        &double_press,
     );
     futures_util::pin_mut!(usb_recv_task);
 
-    let usb_send_task = usb::usb_send_task(&usb_class, &pending_volume_updates, &pending_presses);
+    let usb_send_task = usb::usb_send_task(
+        &usb_class,
+        &pending_volume_updates,
+        &pending_buttons,
+        &pending_hello,
+        &pending_icon_status,
+    );
     futures_util::pin_mut!(usb_send_task);
 
     let mute_buttons_task = mute::mute_buttons_task(
@@ -410,7 +463,8 @@ fn main() -> ! {
         mute_ch2,
         mute_ch3,
         mute_ch4,
-        &pending_presses,
+        &pending_buttons,
+        &double_press,
     );
     futures_util::pin_mut!(mute_buttons_task);
 
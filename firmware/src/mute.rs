@@ -1,5 +1,91 @@
 use crate::ResultWarn;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
+
+/// A button reading has to stay the same this long before it counts.
+const DEBOUNCE_MS: u32 = 20;
+/// Holding a mute button down this long counts as a long press.
+const LONG_PRESS_MS: u32 = 1000;
+/// A second press within this time after releasing the button makes a double press.
+const DOUBLE_PRESS_MS: u32 = 300;
+
+/// The channels in the order of the buttons passed to [`mute_buttons_task()`].
+const CHANNELS: [common::Channel; 5] = [
+    common::Channel::Main,
+    common::Channel::App(0),
+    common::Channel::App(1),
+    common::Channel::App(2),
+    common::Channel::App(3),
+];
+
+/// Button events waiting to be sent to the host, oldest first.
+pub type PendingButtons = heapless::Deque<(common::Channel, common::ButtonEvent), 16>;
+
+/// Which buttons the host wants double presses from, in the order of [`CHANNELS`].
+pub type DoublePress = [bool; CHANNELS.len()];
+
+/// Gesture detection for a single button.
+#[derive(Debug, Clone, Copy, Default)]
+struct Button {
+    /// The last reading and when it changed.
+    raw: bool,
+    raw_since: u32,
+    /// The debounced state.
+    pressed: bool,
+    /// When the current press started and whether it was reported as a long press already.
+    press: Option<(u32, bool)>,
+    /// When a click was released, while it could still become a double press.
+    click: Option<u32>,
+    /// Whether the current press is the second one of a double press.
+    second: bool,
+}
+
+impl Button {
+    fn read(&mut self, pressed: bool) {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since = crate::cycles();
+        }
+    }
+
+    /// Without `double_press`, clicks are reported right away.
+    fn poll(&mut self, double_press: bool, mut report: impl FnMut(common::ButtonEvent)) {
+        if self.raw != self.pressed && crate::millis_since(self.raw_since) >= DEBOUNCE_MS {
+            self.pressed = self.raw;
+            if self.pressed {
+                report(common::ButtonEvent::Pressed);
+                self.press = Some((crate::cycles(), false));
+                self.second = self.click.take().is_some();
+            } else {
+                report(common::ButtonEvent::Released);
+                match self.press.take() {
+                    Some((_, true)) => (),
+                    Some(_) if self.second => report(common::ButtonEvent::DoublePress),
+                    Some(_) if double_press => self.click = Some(crate::cycles()),
+                    Some(_) => report(common::ButtonEvent::Click),
+                    None => (),
+                }
+                self.second = false;
+            }
+        }
+
+        if let Some((since, long @ false)) = &mut self.press {
+            if crate::millis_since(*since) >= LONG_PRESS_MS {
+                *long = true;
+                // the first press was a click of its own after all
+                if core::mem::take(&mut self.second) {
+                    report(common::ButtonEvent::Click);
+                }
+                report(common::ButtonEvent::LongPress);
+            }
+        }
+        if let Some(released) = self.click {
+            if crate::millis_since(released) >= DOUBLE_PRESS_MS {
+                self.click = None;
+                report(common::ButtonEvent::Click);
+            }
+        }
+    }
+}
 
 pub async fn mute_buttons_task<'a, E, M, I2C, EBUS>(
     pca_int: impl embedded_hal::digital::v2::InputPin<Error = E>,
@@ -8,57 +94,43 @@ pub async fn mute_buttons_task<'a, E, M, I2C, EBUS>(
     mute_ch2: port_expander::Pin<'a, port_expander::mode::Input, M>,
     mute_ch3: port_expander::Pin<'a, port_expander::mode::Input, M>,
     mute_ch4: port_expander::Pin<'a, port_expander::mode::Input, M>,
-    pending_presses: &RefCell<heapless::LinearMap<common::Channel, (), 5>>,
+    pending_buttons: &RefCell<PendingButtons>,
+    double_press: &Cell<DoublePress>,
 ) where
     E: core::fmt::Debug,
     M: shared_bus::BusMutex<Bus = port_expander::dev::pca9555::Driver<I2C>>,
     I2C: port_expander::I2cBus<BusError = EBUS>,
     EBUS: core::fmt::Debug,
 {
+    let mut buttons = [Button::default(); 5];
     loop {
-        if pca_int.is_high().unwrap() {
-            // nothing happened...
-            cassette::yield_now().await;
-            continue;
-        }
-
-        let buttons = match port_expander::read_multiple([
-            &mute_main, &mute_ch1, &mute_ch2, &mute_ch3, &mute_ch4,
-        ]) {
-            Ok(b) => b,
-            e => {
-                e.err_warn("Failed reading buttons");
-                continue;
+        // The interrupt is only asserted when a button changed.
+        if pca_int.is_low().unwrap() {
+            match port_expander::read_multiple([
+                &mute_main, &mute_ch1, &mute_ch2, &mute_ch3, &mute_ch4,
+            ]) {
+                Ok(readings) => {
+                    for (button, released) in buttons.iter_mut().zip(readings.iter()) {
+                        button.read(!released);
+                    }
+                }
+                e => e.err_warn("Failed reading buttons"),
             }
-        };
-
-        let mut pending_presses = pending_presses.borrow_mut();
-        if !buttons[0] {
-            pending_presses
-                .insert(common::Channel::Main, ())
-                .err_warn("Failed reporting a button event");
-        }
-        if !buttons[1] {
-            pending_presses
-                .insert(common::Channel::Ch1, ())
-                .err_warn("Failed reporting a button event");
         }
-        if !buttons[2] {
-            pending_presses
-                .insert(common::Channel::Ch2, ())
-                .err_warn("Failed reporting a button event");
-        }
-        if !buttons[3] {
-            pending_presses
-                .insert(common::Channel::Ch3, ())
-                .err_warn("Failed reporting a button event");
-        }
-        if !buttons[4] {
-            pending_presses
-                .insert(common::Channel::Ch4, ())
-                .err_warn("Failed reporting a button event");
+
+        let double_press = double_press.get();
+        for ((ch, button), &double_press) in CHANNELS
+            .iter()
+            .zip(buttons.iter_mut())
+            .zip(double_press.iter())
+        {
+            button.poll(double_press, |event| {
+                pending_buttons
+                    .borrow_mut()
+                    .push_back((*ch, event))
+                    .err_warn("Failed reporting a button event");
+            });
         }
-        drop(pending_presses);
 
         cassette::yield_now().await;
     }
@@ -5,6 +5,16 @@ pub enum Led {
     Off,
 }
 
+impl From<common::ChannelState> for Led {
+    fn from(state: common::ChannelState) -> Self {
+        match state {
+            common::ChannelState::Inactive => Led::Off,
+            common::ChannelState::Running => Led::Green,
+            common::ChannelState::Muted => Led::Red,
+        }
+    }
+}
+
 pub struct ChannelStatusLeds<S, L1, L2> {
     pub sync_led: S,
     pub button_led1: L1,
@@ -17,7 +27,6 @@ where
     L1: embedded_hal::digital::v2::OutputPin<Error = E>,
     L2: embedded_hal::digital::v2::OutputPin<Error = E>,
 {
-    // TODO: Do we even need the sync_leds anymore?
     pub fn set_sync(&mut self, state: bool) -> Result<(), S::Error> {
         if state {
             self.sync_led.set_low()
@@ -27,12 +36,7 @@ where
     }
 
     pub fn set_button_led_state(&mut self, state: common::ChannelState) -> Result<(), E> {
-        let led = match state {
-            common::ChannelState::Inactive => Led::Off,
-            common::ChannelState::Running => Led::Green,
-            common::ChannelState::Muted => Led::Red,
-        };
-        self.set_button_led(led)
+        self.set_button_led(state.into())
     }
 
     pub fn set_button_led(&mut self, state: Led) -> Result<(), E> {
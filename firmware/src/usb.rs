@@ -1,5 +1,6 @@
 use crate::display;
 use crate::level;
+use crate::mute;
 use crate::status_leds;
 use crate::ResultWarn;
 use core::cell::{Cell, RefCell};
@@ -141,6 +142,31 @@ impl<'a, B: usb_device::bus::UsbBus> usb_device::class::UsbClass<B> for PavuMixe
     }
 }
 
+/// Length and number of the on-phases when blinking a button LED.
+const BLINK_MS: u32 = 150;
+const BLINK_COUNT: u32 = 3;
+
+struct Blink {
+    ch: common::Channel,
+    since: u32,
+    phase: u32,
+}
+
+/// Index into per-channel arrays, with the main channel first.
+fn slot(ch: common::Channel) -> usize {
+    match ch {
+        common::Channel::Main => 0,
+        common::Channel::App(i) => i as usize + 1,
+    }
+}
+
+fn channel_exists(ch: Option<common::Channel>) -> bool {
+    match ch {
+        Some(common::Channel::App(i)) => i < crate::CHANNEL_COUNT,
+        _ => true,
+    }
+}
+
 pub async fn usb_recv_task<'a, B, E>(
     usb_dev: &mut usb_device::device::UsbDevice<'a, B>,
     usb_class: &RefCell<PavuMixerClass<'a, B>>,
@@ -182,11 +208,19 @@ pub async fn usb_recv_task<'a, B, E>(
         impl OutputPin,
     >,
     pending_forced_update: &Cell<bool>,
+    pending_hello: &Cell<bool>,
+    pending_icon_status: &RefCell<heapless::LinearMap<u16, common::IconStatus, 4>>,
+    double_press: &Cell<mute::DoublePress>,
 ) where
     B: usb_device::bus::UsbBus,
     E: core::fmt::Debug,
 {
     let mut suspend = true;
+    // Last state of each channel, to restore the button LED after blinking.
+    let mut states = [common::ChannelState::Inactive; crate::CHANNEL_COUNT as usize + 1];
+    let mut blink: Option<Blink> = None;
+    // Peak-hold marker of the main level meter, the channel LEDs cannot show one.
+    let mut main_hold = 0.0;
     loop {
         let new_suspend = match usb_dev.state() {
             usb_device::device::UsbDeviceState::Suspend => true,
@@ -203,6 +237,7 @@ pub async fn usb_recv_task<'a, B, E>(
                 let _ = ch3_level.update_level(0.0);
                 let _ = ch4_level.update_level(0.0);
                 let _ = main_level.update_level(0.0);
+                main_hold = 0.0;
                 let _ = ch1_leds.set_button_led_state(common::ChannelState::Inactive);
                 let _ = ch2_leds.set_button_led_state(common::ChannelState::Inactive);
                 let _ = ch3_leds.set_button_led_state(common::ChannelState::Inactive);
@@ -220,6 +255,32 @@ pub async fn usb_recv_task<'a, B, E>(
 
         suspend = new_suspend;
 
+        if let Some(b) = &mut blink {
+            let phase = crate::millis_since(b.since) / BLINK_MS;
+            if phase != b.phase {
+                b.phase = phase;
+                let led = if phase >= 2 * BLINK_COUNT {
+                    status_leds::Led::from(states[slot(b.ch)])
+                } else if phase % 2 == 0 {
+                    status_leds::Led::Off
+                } else {
+                    status_leds::Led::Green
+                };
+                match b.ch {
+                    common::Channel::Main => main_leds.set_button_led(led),
+                    common::Channel::App(0) => ch1_leds.set_button_led(led),
+                    common::Channel::App(1) => ch2_leds.set_button_led(led),
+                    common::Channel::App(2) => ch3_leds.set_button_led(led),
+                    common::Channel::App(3) => ch4_leds.set_button_led(led),
+                    common::Channel::App(_) => Ok(()),
+                }
+                .err_warn("Failed setting LEDs");
+            }
+            if phase >= 2 * BLINK_COUNT {
+                blink = None;
+            }
+        }
+
         if {
             let mut usb_class = usb_class.borrow_mut();
             !usb_dev.poll(&mut [&mut *usb_class])
@@ -228,15 +289,27 @@ pub async fn usb_recv_task<'a, B, E>(
             continue;
         }
 
-        // If we're currently expecting icon data, try polling for it.
-        gui.try_push_icon_data_if_active(|buf| match usb_class.borrow_mut().recv_bulk(buf) {
-            Err(Error::WouldBlock) => 0,
-            Ok(len) => len,
-            Err(e) => {
-                rprintln!("USB read error: {:?}", e);
-                0
-            }
-        });
+        // Icon frames can arrive at any time.
+        let mut packet = [0x00; 64];
+        match {
+            let mut usb_class = usb_class.borrow_mut();
+            usb_class.recv_bulk(&mut packet)
+        } {
+            Err(Error::WouldBlock) => (),
+            Err(e) => rprintln!("USB read error: {:?}", e),
+            Ok(len) => gui.push_icon_packet(&packet[..len], |sequence, status| {
+                if status != common::IconStatus::Shown {
+                    rprintln!("Icon transfer {} failed: {:?}", sequence, status);
+                }
+                if pending_icon_status
+                    .borrow_mut()
+                    .insert(sequence, status)
+                    .is_err()
+                {
+                    rprintln!("Dropping status of icon transfer {}.", sequence);
+                }
+            }),
+        }
 
         match {
             let mut usb_class = usb_class.borrow_mut();
@@ -244,67 +317,122 @@ pub async fn usb_recv_task<'a, B, E>(
         } {
             Err(Error::WouldBlock) => (),
             Err(e) => rprintln!("USB read error: {:?}", e),
+            Ok(msg) if !channel_exists(msg.channel()) => {
+                rprintln!("Ignoring message for unknown channel: {:?}", msg);
+            }
             Ok(msg) => match msg {
-                common::HostMessage::UpdatePeak(common::Channel::Main, v) => {
-                    main_level.update_level(v);
-                }
-                common::HostMessage::UpdatePeak(ch, v) => match ch {
-                    common::Channel::Ch1 => ch1_level.update_level(v),
-                    common::Channel::Ch2 => ch2_level.update_level(v),
-                    common::Channel::Ch3 => ch3_level.update_level(v),
-                    common::Channel::Ch4 => ch4_level.update_level(v),
-                    _ => unreachable!(),
-                },
-                common::HostMessage::UpdateChannelState(ch, state) => match ch {
-                    common::Channel::Main => {
-                        main_leds
-                            .set_button_led_state(state)
-                            .err_warn("Failed setting LEDs");
-                    }
-                    common::Channel::Ch1 => {
-                        ch1_leds
-                            .set_button_led_state(state)
-                            .err_warn("Failed setting LEDs");
-                        if !state.is_active() {
-                            ch1_level.update_level(0.0);
-                            gui.clear_icon(ch);
+                common::HostMessage::UpdatePeaks(main, peaks) => {
+                    main_level
+                        .update_level_with_hold(common::level_from_quantized(main), main_hold);
+                    for (index, &peak) in peaks.iter().enumerate() {
+                        let ch = common::Channel::from_index(index);
+                        // the host keeps sending the last level of channels which went inactive
+                        let v = if channel_exists(Some(ch)) && states[slot(ch)].is_active() {
+                            common::level_from_quantized(peak)
+                        } else {
+                            0.0
+                        };
+                        match ch {
+                            common::Channel::App(0) => ch1_level.update_level(v),
+                            common::Channel::App(1) => ch2_level.update_level(v),
+                            common::Channel::App(2) => ch3_level.update_level(v),
+                            common::Channel::App(3) => ch4_level.update_level(v),
+                            _ => (),
                         }
                     }
-                    common::Channel::Ch2 => {
-                        ch2_leds
-                            .set_button_led_state(state)
-                            .err_warn("Failed setting LEDs");
-                        if !state.is_active() {
-                            ch2_level.update_level(0.0);
-                            gui.clear_icon(ch);
+                }
+                common::HostMessage::UpdateChannelState(ch, state) => {
+                    states[slot(ch)] = state;
+                    match ch {
+                        common::Channel::Main => {
+                            main_leds
+                                .set_button_led_state(state)
+                                .err_warn("Failed setting LEDs");
                         }
-                    }
-                    common::Channel::Ch3 => {
-                        ch3_leds
-                            .set_button_led_state(state)
-                            .err_warn("Failed setting LEDs");
-                        if !state.is_active() {
-                            ch3_level.update_level(0.0);
-                            gui.clear_icon(ch);
+                        common::Channel::App(0) => {
+                            ch1_leds
+                                .set_button_led_state(state)
+                                .err_warn("Failed setting LEDs");
+                            if !state.is_active() {
+                                ch1_level.update_level(0.0);
+                                gui.clear_icon(ch);
+                            }
                         }
-                    }
-                    common::Channel::Ch4 => {
-                        ch4_leds
-                            .set_button_led_state(state)
-                            .err_warn("Failed setting LEDs");
-                        if !state.is_active() {
-                            ch4_level.update_level(0.0);
-                            gui.clear_icon(ch);
+                        common::Channel::App(1) => {
+                            ch2_leds
+                                .set_button_led_state(state)
+                                .err_warn("Failed setting LEDs");
+                            if !state.is_active() {
+                                ch2_level.update_level(0.0);
+                                gui.clear_icon(ch);
+                            }
+                        }
+                        common::Channel::App(2) => {
+                            ch3_leds
+                                .set_button_led_state(state)
+                                .err_warn("Failed setting LEDs");
+                            if !state.is_active() {
+                                ch3_level.update_level(0.0);
+                                gui.clear_icon(ch);
+                            }
                         }
+                        common::Channel::App(3) => {
+                            ch4_leds
+                                .set_button_led_state(state)
+                                .err_warn("Failed setting LEDs");
+                            if !state.is_active() {
+                                ch4_level.update_level(0.0);
+                                gui.clear_icon(ch);
+                            }
+                        }
+                        common::Channel::App(_) => unreachable!(),
                     }
-                },
-                common::HostMessage::SetIcon(ch) => {
-                    gui.start_icon_stream(ch);
+                }
+                common::HostMessage::SetPeakHold(common::Channel::Main, hold) => {
+                    main_hold = hold;
+                }
+                common::HostMessage::SetPeakHold(_, _) => (),
+                common::HostMessage::SetLabel(ch, label) => {
+                    gui.set_label(ch, &label);
+                }
+                common::HostMessage::SetDoublePress(ch, enabled) => {
+                    let mut channels = double_press.get();
+                    channels[slot(ch)] = enabled;
+                    double_press.set(channels);
+                }
+                common::HostMessage::Blink(ch) => {
+                    blink = Some(Blink {
+                        ch,
+                        since: crate::cycles(),
+                        phase: u32::MAX,
+                    });
+                }
+                common::HostMessage::Hello(version) => {
+                    rprintln!("Host speaks protocol version {}.", version);
+                    pending_hello.set(true);
                 }
                 common::HostMessage::ForceUpdate => {
                     rprintln!("Forcing an update.");
                     pending_forced_update.set(true);
                 }
+                common::HostMessage::SetSyncLed(ch, state) => match ch {
+                    common::Channel::Main => {
+                        main_leds.set_sync(state).err_warn("Failed setting LEDs")
+                    }
+                    common::Channel::App(0) => {
+                        ch1_leds.set_sync(state).err_warn("Failed setting LEDs")
+                    }
+                    common::Channel::App(1) => {
+                        ch2_leds.set_sync(state).err_warn("Failed setting LEDs")
+                    }
+                    common::Channel::App(2) => {
+                        ch3_leds.set_sync(state).err_warn("Failed setting LEDs")
+                    }
+                    common::Channel::App(3) => {
+                        ch4_leds.set_sync(state).err_warn("Failed setting LEDs")
+                    }
+                    common::Channel::App(_) => unreachable!(),
+                },
             },
         }
     }
@@ -313,28 +441,58 @@ pub async fn usb_recv_task<'a, B, E>(
 pub async fn usb_send_task<'a, B>(
     usb_class: &RefCell<PavuMixerClass<'a, B>>,
     pending_volume_updates: &RefCell<heapless::LinearMap<common::Channel, f32, 5>>,
-    pending_presses: &RefCell<heapless::LinearMap<common::Channel, (), 5>>,
+    pending_buttons: &RefCell<mute::PendingButtons>,
+    pending_hello: &Cell<bool>,
+    pending_icon_status: &RefCell<heapless::LinearMap<u16, common::IconStatus, 4>>,
 ) where
     B: usb_device::bus::UsbBus,
 {
     loop {
-        for ch in &[
-            common::Channel::Main,
-            common::Channel::Ch1,
-            common::Channel::Ch2,
-            common::Channel::Ch3,
-            common::Channel::Ch4,
-        ] {
-            let maybe_pressed = pending_presses.borrow().get(ch).cloned();
-            if let Some(()) = maybe_pressed {
-                let msg = common::DeviceMessage::ToggleChannelMute(*ch);
+        if pending_hello.get() {
+            let msg = common::DeviceMessage::Hello(crate::device_info());
+            if let Err(e) = PavuMixerClass::send_device_message_async(usb_class, msg).await {
+                rprintln!("USB write error: {:?}", e);
+            } else {
+                pending_hello.set(false);
+            }
+        }
+
+        let maybe_status = pending_icon_status
+            .borrow()
+            .iter()
+            .next()
+            .map(|(sequence, status)| (*sequence, *status));
+        if let Some((sequence, status)) = maybe_status {
+            let msg = common::DeviceMessage::IconStatus(sequence, status);
+            if let Err(e) = PavuMixerClass::send_device_message_async(usb_class, msg).await {
+                rprintln!("USB write error: {:?}", e);
+            } else {
+                pending_icon_status.borrow_mut().remove(&sequence);
+            }
+        }
+
+        // Button events are sent in order, a click only makes sense after the press.
+        loop {
+            let maybe_event = pending_buttons.borrow().front().copied();
+            if let Some((ch, event)) = maybe_event {
+                let msg = common::DeviceMessage::Button(ch, event);
                 if let Err(e) = PavuMixerClass::send_device_message_async(usb_class, msg).await {
                     rprintln!("USB write error: {:?}", e);
                 } else {
-                    pending_presses.borrow_mut().remove(ch);
+                    pending_buttons.borrow_mut().pop_front();
+                    continue;
                 }
             }
+            break;
+        }
 
+        for ch in &[
+            common::Channel::Main,
+            common::Channel::App(0),
+            common::Channel::App(1),
+            common::Channel::App(2),
+            common::Channel::App(3),
+        ] {
             // The loop for the volume update works differently to ensure we will always send the
             // most recent value possible.
             loop {
@@ -0,0 +1,360 @@
+//! Abstraction over the audio server the daemon talks to.
+//!
+//! The mixer logic only ever sees the types in this module.  Each backend translates its native
+//! objects into these and pushes them as [`Event`]s to the application.
+use crate::config;
+use crate::desktop;
+use std::collections;
+use std::sync::mpsc;
+
+/// Properties of a stream or device, as reported by the audio server.
+pub type Properties = collections::BTreeMap<String, String>;
+
+/// Identifies the audio server object a stream is controlling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamId {
+    Sink(u32),
+    SinkInput(u32),
+    Source(u32),
+    SourceOutput(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkInputInfo {
+    pub index: u32,
+    pub name: Option<String>,
+    pub application: Option<String>,
+    pub connected_sink: u32,
+    pub properties: Properties,
+    /// Per-channel volumes, `1.0` being 100%.
+    pub volume: Vec<f32>,
+    pub mute: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceOutputInfo {
+    pub index: u32,
+    pub name: Option<String>,
+    pub application: Option<String>,
+    pub connected_source: u32,
+    pub properties: Properties,
+    /// Per-channel volumes, `1.0` being 100%.
+    pub volume: Vec<f32>,
+    pub mute: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkInfo {
+    pub index: u32,
+    pub name: Option<String>,
+    pub monitoring_source: u32,
+    /// Per-channel volumes, `1.0` being 100%.
+    pub volume: Vec<f32>,
+    pub mute: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceInfo {
+    pub index: u32,
+    pub name: Option<String>,
+    pub properties: Properties,
+    /// Per-channel volumes, `1.0` being 100%.
+    pub volume: Vec<f32>,
+    pub mute: bool,
+}
+
+/// Per-channel volumes relative to the loudest channel, or `None` if all channels are silent.
+pub fn balance(volume: &[f32]) -> Option<Vec<f32>> {
+    let max = volume.iter().copied().fold(0.0, f32::max);
+    if max > 0.0 {
+        Some(volume.iter().map(|v| v / max).collect())
+    } else {
+        None
+    }
+}
+
+/// Scale per-channel volumes so the loudest channel ends up at `v`.
+///
+/// The channels keep the ratios from `balance` (see [`balance()`]), so a balance set e.g. in
+/// `pavucontrol` survives fader changes (like `pa_cvolume_scale()`), even when the fader was all
+/// the way down in between.  If `balance` does not fit the channels, every channel is set to `v`.
+pub fn scale_volume(volume: &mut [f32], balance: &[f32], v: f32) {
+    if balance.len() == volume.len() {
+        for (channel_volume, ratio) in volume.iter_mut().zip(balance) {
+            *channel_volume = v * ratio;
+        }
+    } else {
+        for channel_volume in volume.iter_mut() {
+            *channel_volume = v;
+        }
+    }
+}
+
+/// Information about the object a [`Stream`] is controlling.
+#[derive(Debug, Clone)]
+pub enum StreamInfo {
+    Sink(SinkInfo),
+    SinkInput(SinkInputInfo),
+    Source(SourceInfo),
+    SourceOutput(SourceOutputInfo),
+}
+
+impl StreamInfo {
+    pub fn id(&self) -> StreamId {
+        match self {
+            StreamInfo::Sink(s) => StreamId::Sink(s.index),
+            StreamInfo::SinkInput(s) => StreamId::SinkInput(s.index),
+            StreamInfo::Source(s) => StreamId::Source(s.index),
+            StreamInfo::SourceOutput(s) => StreamId::SourceOutput(s.index),
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            StreamInfo::Sink(s) => format!("Sink '{}'", s.name.as_deref().unwrap_or("unknown")),
+            StreamInfo::SinkInput(s) => {
+                format!("Sink-Input '{}'", s.name.as_deref().unwrap_or("unknown"))
+            }
+            StreamInfo::Source(s) => {
+                format!("Source '{}'", s.name.as_deref().unwrap_or("unknown"))
+            }
+            StreamInfo::SourceOutput(s) => {
+                format!("Source-Output '{}'", s.name.as_deref().unwrap_or("unknown"))
+            }
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            StreamInfo::Sink(s) => s.name.as_deref(),
+            StreamInfo::SinkInput(s) => s.name.as_deref(),
+            StreamInfo::Source(s) => s.name.as_deref(),
+            StreamInfo::SourceOutput(s) => s.name.as_deref(),
+        }
+    }
+
+    pub fn properties(&self) -> Option<&Properties> {
+        match self {
+            StreamInfo::Sink(_) => None,
+            StreamInfo::SinkInput(s) => Some(&s.properties),
+            StreamInfo::Source(s) => Some(&s.properties),
+            StreamInfo::SourceOutput(s) => Some(&s.properties),
+        }
+    }
+
+    pub fn volume(&self) -> &[f32] {
+        match self {
+            StreamInfo::Sink(s) => &s.volume,
+            StreamInfo::SinkInput(s) => &s.volume,
+            StreamInfo::Source(s) => &s.volume,
+            StreamInfo::SourceOutput(s) => &s.volume,
+        }
+    }
+
+    pub fn volume_mut(&mut self) -> &mut Vec<f32> {
+        match self {
+            StreamInfo::Sink(s) => &mut s.volume,
+            StreamInfo::SinkInput(s) => &mut s.volume,
+            StreamInfo::Source(s) => &mut s.volume,
+            StreamInfo::SourceOutput(s) => &mut s.volume,
+        }
+    }
+
+    pub fn muted(&self) -> bool {
+        match self {
+            StreamInfo::Sink(s) => s.mute,
+            StreamInfo::SinkInput(s) => s.mute,
+            StreamInfo::Source(s) => s.mute,
+            StreamInfo::SourceOutput(s) => s.mute,
+        }
+    }
+
+    pub fn set_muted(&mut self, mute: bool) {
+        match self {
+            StreamInfo::Sink(s) => s.mute = mute,
+            StreamInfo::SinkInput(s) => s.mute = mute,
+            StreamInfo::Source(s) => s.mute = mute,
+            StreamInfo::SourceOutput(s) => s.mute = mute,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Event<S> {
+    /// After querying the default sink, the backend came back with this stream.
+    NewDefaultSink(S),
+    /// A new sink-input showed up and we need to check whether it matches any of our channels - if
+    /// yes, it should be attached.
+    SinkInputAdded(SinkInputInfo),
+    /// Some property of a sink-input changed.  It potentially needs to be re-attached to a
+    /// different channel.
+    SinkInputChanged(SinkInputInfo),
+    /// A sink-input was removed and we should probably drop it from a potentially connected
+    /// channel as well.
+    SinkInputRemoved(u32),
+    /// A new sink-input stream is available for the given channel.
+    NewSinkInput(common::Channel, S),
+    /// A new sink showed up and should be attached if a channel is configured for it.
+    SinkAdded(SinkInfo),
+    /// Some property of a sink changed (e.g. its volume was changed externally).
+    SinkChanged(SinkInfo),
+    /// A sink was removed and must be dropped from its channel.
+    SinkRemoved(u32),
+    /// A new sink stream is available for the given channel.
+    NewSink(common::Channel, S),
+    /// A new source showed up and should be attached if a channel is configured for it.
+    SourceAdded(SourceInfo),
+    /// Some property of a source changed (e.g. it was muted externally).
+    SourceChanged(SourceInfo),
+    /// A source was removed and must be dropped from its channel.
+    SourceRemoved(u32),
+    /// A new source stream is available for the given channel.
+    NewSource(common::Channel, S),
+    /// A new source-output (recording application) showed up and we need to check whether it
+    /// matches any of our channels - if yes, it should be attached.
+    SourceOutputAdded(SourceOutputInfo),
+    /// Some property of a source-output changed.  It potentially needs to be re-attached to a
+    /// different channel.
+    SourceOutputChanged(SourceOutputInfo),
+    /// A source-output was removed and we should drop it from a potentially connected channel.
+    SourceOutputRemoved(u32),
+    /// A new source-output stream is available for the given channel.
+    NewSourceOutput(common::Channel, S),
+    /// New signal peak information is available for this stream (sink / sink-input).
+    NewPeakData(common::Channel, usize),
+}
+
+/// An audio server the daemon can control.
+pub trait Backend: Sized {
+    type Stream: Stream<Backend = Self>;
+
+    /// Take the receiver for all [`Event`]s of this backend.  Can only be called once.
+    fn take_event_receiver(&mut self) -> Option<mpsc::Receiver<Event<Self::Stream>>>;
+
+    /// Process pending work of the backend, optionally blocking until something happened.
+    fn iterate(&mut self, block: bool) -> anyhow::Result<()>;
+
+    /// Announce all existing sinks, sink-inputs, sources and source-outputs again, as if they had
+    /// just been added.
+    fn rescan(&mut self);
+
+    /// Request a stream for a sink-input.  It is delivered as an [`Event::NewSinkInput`].
+    fn request_sink_input_stream(&mut self, info: SinkInputInfo, for_channel: common::Channel);
+
+    /// Request a stream for a sink.  It is delivered as an [`Event::NewSink`].
+    fn request_sink_stream(
+        &mut self,
+        info: SinkInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()>;
+
+    /// Request a stream for a source.  It is delivered as an [`Event::NewSource`].
+    fn request_source_stream(
+        &mut self,
+        info: SourceInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()>;
+
+    /// Request a stream for a source-output.  It is delivered as an [`Event::NewSourceOutput`].
+    fn request_source_output_stream(
+        &mut self,
+        info: SourceOutputInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()>;
+
+    /// Make the sink with this name the default output.  The change is announced as an
+    /// [`Event::NewDefaultSink`] once the audio server applied it.
+    fn set_default_sink(&mut self, name: &str);
+}
+
+/// A stream controlling (and monitoring) one object of the audio server.
+pub trait Stream: std::fmt::Debug {
+    type Backend;
+
+    fn info(&self) -> &StreamInfo;
+
+    fn info_mut(&mut self) -> &mut StreamInfo;
+
+    /// Start peak monitoring.
+    fn connect(&mut self) -> anyhow::Result<()>;
+
+    /// Set the channel this stream reports its peak data for.
+    fn set_connected_channel(&self, ch: common::Channel, index: usize);
+
+    fn get_recent_peak(&mut self) -> anyhow::Result<Option<f32>>;
+
+    /// Push the per-channel volumes of [`Stream::info`] to the audio server.
+    fn apply_volume(&mut self, backend: &mut Self::Backend);
+
+    fn set_mute(&mut self, backend: &mut Self::Backend, mute: bool);
+
+    /// Set the volume of the loudest channel to `v`, with the other channels following the given
+    /// `balance` (see [`scale_volume()`]).
+    fn set_volume(&mut self, backend: &mut Self::Backend, v: f32, balance: &[f32]) {
+        scale_volume(self.info_mut().volume_mut(), balance, v);
+        self.apply_volume(backend);
+    }
+
+    fn id(&self) -> StreamId {
+        self.info().id()
+    }
+
+    fn is_mute(&self) -> bool {
+        self.info().muted()
+    }
+
+    /// Update the locally known mute state after it was changed externally.
+    fn sync_mute(&mut self, mute: bool) {
+        self.info_mut().set_muted(mute);
+    }
+
+    fn get_icon_name(&self, icon_mappings: &[config::IconMapping]) -> Option<String> {
+        let properties = match self.info() {
+            StreamInfo::Sink(_) => return None,
+            StreamInfo::Source(info) => return info.properties.get("device.icon_name").cloned(),
+            StreamInfo::SinkInput(info) => &info.properties,
+            StreamInfo::SourceOutput(info) => &info.properties,
+        };
+
+        let mapping = icon_mappings.iter().find(|mapping| {
+            mapping.property_matches.iter().all(|(name, value)| {
+                properties
+                    .get(name)
+                    .map(|actual| value.is_match(actual))
+                    .unwrap_or(false)
+            })
+        });
+        if let Some(mapping) = mapping {
+            return Some(mapping.icon.clone());
+        }
+
+        properties
+            .get("application.icon_name")
+            .cloned()
+            .or_else(|| desktop::icon_for(properties))
+    }
+
+    /// Name to show for this stream on the display.
+    fn get_label(&self) -> Option<String> {
+        match self.info() {
+            StreamInfo::Sink(_) => None,
+            StreamInfo::Source(info) => info
+                .properties
+                .get("device.description")
+                .cloned()
+                .or_else(|| info.name.clone()),
+            StreamInfo::SinkInput(info) => info.application.clone().or_else(|| info.name.clone()),
+            StreamInfo::SourceOutput(info) => {
+                info.application.clone().or_else(|| info.name.clone())
+            }
+        }
+    }
+
+    fn sink_name(&self) -> Option<String> {
+        if let StreamInfo::Sink(info) = self.info() {
+            info.name.clone()
+        } else {
+            None
+        }
+    }
+}
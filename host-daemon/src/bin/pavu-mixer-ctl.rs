@@ -0,0 +1,47 @@
+//! Command-line client for the control socket of the Pavu Mixer daemon.
+use anyhow::Context;
+use std::io::{Read, Write};
+use std::os::unix::net;
+
+#[path = "../socket.rs"]
+mod socket;
+
+const USAGE: &str = "\
+Usage: pavu-mixer-ctl <command> [<args>...]
+
+Commands:
+    status                      Show channels with their streams, volume and mute state
+    streams                     List all sink-inputs with their properties
+    assign <stream> <channel>   Attach the sink-input with index <stream> to a channel
+    pin <stream> <channel>      Like assign, but also remember it for the stream's application
+    unpin <stream>              Forget the pinned channel of the stream's application
+    mute <channel>              Toggle the mute state of a channel
+    volume <channel> <percent>  Set the volume of all streams on a channel
+    profile [<name>]            List the profiles or switch to another one
+
+Channels are named \"main\" or by their number, starting at 1.";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let path = socket::socket_path()?;
+    let mut connection = net::UnixStream::connect(&path)
+        .with_context(|| format!("failed connecting to the daemon at {:?}", path))?;
+    connection.write_all(args.join("\n").as_bytes())?;
+    connection.shutdown(std::net::Shutdown::Write)?;
+
+    let mut reply = String::new();
+    connection
+        .read_to_string(&mut reply)
+        .context("failed reading reply from the daemon")?;
+    if reply.starts_with("error: ") {
+        eprint!("{}", reply);
+        std::process::exit(1);
+    }
+    print!("{}", reply);
+    Ok(())
+}
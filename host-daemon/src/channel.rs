@@ -1,58 +1,195 @@
+use crate::backend;
 use crate::config;
+use crate::meter;
+use std::cmp;
+use std::time;
+
+/// Volumes closer than this are considered equal for picking up a stream's volume.
+const PICKUP_TOLERANCE: f32 = 0.01;
+
+/// Volume reports arriving this soon after a fader movement are most likely just our own changes
+/// echoed back by the audio server.
+const ECHO_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
+/// How well a stream matches a channel.  Better matches compare greater.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Match {
+    /// Only matched because the channel takes all otherwise unmatched streams.
+    CatchAll,
+    /// A match set with the given priority matched.
+    Priority(i32),
+}
+
+/// Find the index of the channel a stream belongs to.
+///
+/// The best match wins, ties go to the lower channel.
+pub fn find_best_match<S>(
+    channels: &[Channel<S>],
+    matcher: impl Fn(&Channel<S>) -> Option<Match>,
+) -> Option<usize> {
+    channels
+        .iter()
+        .enumerate()
+        .filter_map(|(index, channel)| matcher(channel).map(|m| (m, cmp::Reverse(index))))
+        .max()
+        .map(|(_, cmp::Reverse(index))| index)
+}
+
+fn property_matches(properties: &backend::Properties, name: &str, value: &config::Pattern) -> bool {
+    properties
+        .get(name)
+        .map(|actual_value| value.is_match(actual_value))
+        .unwrap_or(false)
+}
 
 #[derive(Debug)]
-struct StreamData {
-    stream: crate::pa::Stream,
+struct StreamData<S> {
+    stream: S,
     last_peak: f32,
+    /// Ratios between the stream's channels from when it was last audible, so the balance
+    /// survives the fader going down to zero.
+    balance: Vec<f32>,
+}
+
+impl<S: backend::Stream> StreamData<S> {
+    fn new(stream: S) -> Self {
+        let balance = backend::balance(stream.info().volume()).unwrap_or_default();
+        Self {
+            stream,
+            last_peak: 0.0,
+            balance,
+        }
+    }
+
+    fn set_volume(&mut self, backend: &mut S::Backend, volume: f32, force_mono: bool) {
+        let balance: &[f32] = if force_mono { &[] } else { &self.balance };
+        self.stream.set_volume(backend, volume, balance);
+    }
 }
 
 /// Representation of one of the "physical" mixer channels.
 ///
 /// This explicitly includes the `main` "channel".
 #[derive(Debug)]
-pub struct Channel {
-    /// Attached Pulseaudio streams - their volume is controlled by this channel.
-    attached_streams: slab::Slab<StreamData>,
+pub struct Channel<S> {
+    /// Attached backend streams - their volume is controlled by this channel.
+    attached_streams: slab::Slab<StreamData<S>>,
     /// Property matches for this channel (from the configuration).
     property_matches: Option<config::PropertyMatches>,
+    /// Whether the property matches select playback or recording streams.
+    direction: config::Direction,
+    /// Whether this channel takes all streams no other channel matches.
+    catch_all: bool,
+    /// Name of the source this channel is bound to (from the configuration).
+    source: Option<String>,
+    /// Name of the sink this channel is bound to (from the configuration).
+    sink: Option<String>,
+    /// Whether fader changes flatten the balance of attached streams.
+    force_mono: bool,
+    /// Mapping from fader position to volume.
+    volume_curve: config::VolumeCurve,
+    /// Volume at the top of the fader travel.
+    max_volume: f32,
+    /// Whether the fader needs to pick up the actual volume before it takes control.
+    pickup: bool,
+    /// Whether the fader currently controls the attached streams (only relevant with `pickup`).
+    picked_up: bool,
+    /// When the fader last changed the volume of the attached streams.
+    last_fader_move: Option<time::Instant>,
+    /// Whether the device was last told that the fader and the actual volume disagree.
+    sync_led: bool,
     /// Whether this channel is currently muted.
     mute: bool,
-    /// The current volume for this channel, as last reported by the mixer.
+    /// The current volume for this channel, derived from the fader position last reported by the
+    /// mixer.
     volume: Option<f32>,
+    /// Level meter fed with the peaks of the attached streams.
+    meter: meter::Meter,
+    /// Text shown on the display instead of the name of the attached application.
+    label: Option<String>,
+    /// What the gestures on this channel's button do.
+    buttons: config::Buttons,
 }
 
-impl Channel {
-    pub fn new(property_matches: Option<config::PropertyMatches>) -> Self {
+impl<S: backend::Stream> Channel<S> {
+    pub fn new(config: Option<&config::Channel>, meter: config::Meter) -> Self {
         Self {
             attached_streams: slab::Slab::new(),
-            property_matches,
+            property_matches: config.map(|c| c.property_matches.clone()),
+            direction: config.map(|c| c.direction).unwrap_or_default(),
+            catch_all: config.map(|c| c.catch_all).unwrap_or(false),
+            source: config.and_then(|c| c.source.clone()),
+            sink: config.and_then(|c| c.sink.clone()),
+            force_mono: config.map(|c| c.force_mono).unwrap_or(false),
+            volume_curve: config.map(|c| c.volume_curve).unwrap_or_default(),
+            max_volume: config.and_then(|c| c.max_volume).unwrap_or(1.0),
+            pickup: config.map(|c| c.pickup).unwrap_or(false),
+            picked_up: false,
+            last_fader_move: None,
+            sync_led: false,
             mute: false,
             volume: None,
+            meter: meter::Meter::new(meter),
+            label: config.and_then(|c| c.label.clone()),
+            buttons: config.map(|c| c.buttons).unwrap_or_default(),
         }
     }
 
-    pub fn match_sink_input(&self, info: &crate::pa::SinkInputInfo) -> bool {
-        if let Some(property_matches) = &self.property_matches {
-            'sets_loop: for matches_set in property_matches.iter() {
-                for (name, value) in matches_set.iter() {
-                    let value_regex = regex::Regex::new(value).ok();
-                    if let Some(actual_value) = info.properties.get_str(name).as_ref() {
-                        let verbatim_match = actual_value == value;
-                        let regex_match = value_regex.as_ref().map(|re| re.is_match(actual_value)).unwrap_or(false);
-                        if !verbatim_match && !regex_match {
-                            // this isn't it... try next match set
-                            continue 'sets_loop;
-                        }
-                    } else {
-                        // this isn't it... try next match set
-                        continue 'sets_loop;
-                    }
-                }
-                // only reached after all properties in some match set have been matched
-                return true;
-            }
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn buttons(&self) -> config::Buttons {
+        self.buttons
+    }
+
+    pub fn match_sink(&self, info: &backend::SinkInfo) -> bool {
+        self.sink.is_some() && self.sink == info.name
+    }
+
+    pub fn match_source(&self, info: &backend::SourceInfo) -> bool {
+        self.source.is_some() && self.source == info.name
+    }
+
+    pub fn match_sink_input(&self, info: &backend::SinkInputInfo) -> Option<Match> {
+        if self.direction == config::Direction::Playback {
+            self.match_properties(&info.properties)
+        } else {
+            None
+        }
+    }
+
+    pub fn match_source_output(&self, info: &backend::SourceOutputInfo) -> Option<Match> {
+        if self.direction == config::Direction::Recording {
+            self.match_properties(&info.properties)
+        } else {
+            None
+        }
+    }
+
+    fn match_properties(&self, properties: &backend::Properties) -> Option<Match> {
+        let best_set = self
+            .property_matches
+            .iter()
+            .flat_map(|property_matches| property_matches.iter())
+            .filter(|set| {
+                set.properties
+                    .iter()
+                    .all(|(name, value)| property_matches(properties, name, value))
+            })
+            .filter(|set| {
+                !set.exclude
+                    .iter()
+                    .any(|(name, value)| property_matches(properties, name, value))
+            })
+            .map(|set| Match::Priority(set.priority.unwrap_or(0)))
+            .max();
+
+        if best_set.is_none() && self.catch_all {
+            Some(Match::CatchAll)
+        } else {
+            best_set
         }
-        return false;
     }
 
     /// Detach all currently connected streams.
@@ -66,9 +203,9 @@ impl Channel {
     /// Returns a mutable reference and the index where it was inserted
     pub fn attach_stream(
         &mut self,
-        pa: &mut crate::pa::PulseInterface,
-        mut stream: crate::pa::Stream,
-    ) -> (&mut crate::pa::Stream, usize, common::ChannelState) {
+        backend: &mut S::Backend,
+        mut stream: S,
+    ) -> (&mut S, usize, common::ChannelState) {
         // if this is the first stream, we need to update our local mute information.
         // This will be the initial source of truth for the channel until the device updates it.
         if self.attached_streams.is_empty() {
@@ -77,35 +214,114 @@ impl Channel {
 
         // bring the stream in line with our knowledge
         if stream.is_mute() != self.mute {
-            stream.set_mute(pa, self.mute);
+            stream.set_mute(backend, self.mute);
         }
 
-        if let Some(volume) = self.volume {
-            stream.set_volume(pa, volume);
+        let mut stream_data = StreamData::new(stream);
+        // in pickup mode, the fader has to take over the stream's volume first
+        if let (Some(volume), false) = (self.volume, self.pickup) {
+            stream_data.set_volume(backend, volume, self.force_mono);
         }
 
-        let index = self.attached_streams.insert(StreamData {
-            stream,
-            last_peak: 0.0,
-        });
+        let index = self.attached_streams.insert(stream_data);
+        if self.pickup {
+            self.picked_up = self.fader_matches_volume();
+        }
         let state = self.state();
         (&mut self.attached_streams[index].stream, index, state)
     }
 
-    pub fn index_for_sink_input(&self, sink_input: u32) -> Option<usize> {
+    /// All streams currently attached to this channel.
+    pub fn streams(&self) -> impl Iterator<Item = &S> {
+        self.attached_streams.iter().map(|(_, s)| &s.stream)
+    }
+
+    pub fn index_for_stream(&self, id: backend::StreamId) -> Option<usize> {
         self.attached_streams
             .iter()
-            .filter(|(_, s)| s.stream.is_for_sink_input(sink_input))
+            .filter(|(_, s)| s.stream.id() == id)
             .map(|(i, _)| i)
             .next()
     }
 
-    pub fn try_drop_stream(&mut self, sink_input: u32) -> common::ChannelState {
+    pub fn try_drop_stream(&mut self, id: backend::StreamId) -> common::ChannelState {
         self.attached_streams
-            .retain(|_, stream_data| !stream_data.stream.is_for_sink_input(sink_input));
+            .retain(|_, stream_data| stream_data.stream.id() != id);
         self.state()
     }
 
+    /// Take over the mute state of an attached sink or source after it was changed externally.
+    ///
+    /// Returns the new channel state if it changed.
+    pub fn sync_mute(&mut self, id: backend::StreamId, mute: bool) -> Option<common::ChannelState> {
+        let index = self.index_for_stream(id)?;
+        let previous_state = self.state();
+        self.attached_streams[index].stream.sync_mute(mute);
+        self.mute = mute;
+        let state = self.state();
+        if state != previous_state {
+            Some(state)
+        } else {
+            None
+        }
+    }
+
+    /// Take over the volume of an attached stream after it was changed externally.
+    pub fn sync_volume(&mut self, id: backend::StreamId, volume: &[f32]) {
+        let index = match self.index_for_stream(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let fader_moving = self
+            .last_fader_move
+            .map(|t| t.elapsed() < ECHO_TIMEOUT)
+            .unwrap_or(false);
+        if fader_moving && (self.picked_up || !self.pickup) {
+            return;
+        }
+
+        let stream_data = &mut self.attached_streams[index];
+        *stream_data.stream.info_mut().volume_mut() = volume.to_vec();
+        if let Some(balance) = backend::balance(volume) {
+            stream_data.balance = balance;
+        }
+        if self.pickup {
+            self.picked_up = self.fader_matches_volume();
+        }
+    }
+
+    /// The actual volume of the loudest attached stream.
+    pub fn actual_volume(&self) -> Option<f32> {
+        self.attached_streams
+            .iter()
+            .flat_map(|(_, s)| s.stream.info().volume().iter().copied())
+            .reduce(f32::max)
+    }
+
+    fn fader_matches_volume(&self) -> bool {
+        match (self.volume, self.actual_volume()) {
+            (Some(volume), Some(actual)) => (volume - actual).abs() <= PICKUP_TOLERANCE,
+            _ => false,
+        }
+    }
+
+    /// Whether the fader does not control the attached streams right now because it did not pick
+    /// up their volume yet.
+    pub fn out_of_sync(&self) -> bool {
+        self.pickup && !self.picked_up && !self.attached_streams.is_empty()
+    }
+
+    /// Returns the new state for the sync LED if it needs to be updated.
+    pub fn take_sync_change(&mut self) -> Option<bool> {
+        let out_of_sync = self.out_of_sync();
+        if out_of_sync != self.sync_led {
+            self.sync_led = out_of_sync;
+            Some(out_of_sync)
+        } else {
+            None
+        }
+    }
+
     pub fn update_peak(&mut self, index: usize) -> anyhow::Result<f32> {
         if self.attached_streams.contains(index) {
             match self.attached_streams[index].stream.get_recent_peak() {
@@ -124,17 +340,58 @@ impl Channel {
             .unwrap_or(0.0))
     }
 
-    pub fn update_volume(&mut self, pa: &mut crate::pa::PulseInterface, volume: f32) {
-        self.volume = Some(volume);
+    /// Feed the peak level of the attached streams into the channel's meter.
+    pub fn update_meter(&mut self, peak: f32) -> meter::Reading {
+        self.meter.update(peak, time::Instant::now())
+    }
+
+    /// Replace the meter settings, e.g. after the configuration changed.
+    pub fn set_meter(&mut self, meter: config::Meter) {
+        self.meter = meter::Meter::new(meter);
+    }
+
+    /// Apply a new fader position to all attached streams.
+    pub fn update_volume(&mut self, backend: &mut S::Backend, fader: f32) {
+        let volume = self.volume_curve.volume_for_fader(fader) * self.max_volume;
+        let previous = self.volume.replace(volume);
+
+        if self.pickup && !self.picked_up {
+            match (self.actual_volume(), previous) {
+                // nothing attached that could jump
+                (None, _) => (),
+                // the fader crossed the actual volume
+                (Some(actual), Some(previous))
+                    if (previous - actual) * (volume - actual) <= 0.0 => {}
+                (Some(actual), _) if (volume - actual).abs() <= PICKUP_TOLERANCE => (),
+                _ => return,
+            }
+            self.picked_up = true;
+        }
+
+        self.last_fader_move = Some(time::Instant::now());
         for (_, stream_data) in self.attached_streams.iter_mut() {
-            stream_data.stream.set_volume(pa, volume);
+            stream_data.set_volume(backend, volume, self.force_mono);
         }
     }
 
-    pub fn toggle_mute(&mut self, pa: &mut crate::pa::PulseInterface) -> common::ChannelState {
-        self.mute = !self.mute;
+    /// Set the volume of all attached streams, regardless of the fader position.
+    pub fn set_volume(&mut self, backend: &mut S::Backend, volume: f32) {
         for (_, stream_data) in self.attached_streams.iter_mut() {
-            stream_data.stream.set_mute(pa, self.mute);
+            stream_data.set_volume(backend, volume, self.force_mono);
+        }
+        if self.pickup {
+            self.picked_up = self.fader_matches_volume();
+        }
+    }
+
+    pub fn toggle_mute(&mut self, backend: &mut S::Backend) -> common::ChannelState {
+        self.set_mute(backend, !self.mute)
+    }
+
+    pub fn set_mute(&mut self, backend: &mut S::Backend, mute: bool) -> common::ChannelState {
+        self.mute = mute;
+        for (_, stream_data) in self.attached_streams.iter_mut() {
+            stream_data.stream.set_mute(backend, self.mute);
         }
         self.state()
     }
@@ -149,3 +406,398 @@ impl Channel {
         }
     }
 }
+
+/// Channel the streams of sink-inputs no channel claims are requested for.
+pub const UNASSIGNED: common::Channel = common::Channel::App(u8::MAX);
+
+/// Peaks below this are considered silence when looking for the loudest unassigned stream.
+const SILENCE: f32 = 0.01;
+
+/// Streams of sink-inputs which are not attached to any channel.
+///
+/// They are only monitored for their peaks so learn mode can pick the one which is playing.  Their
+/// volume and mute state are left alone.
+#[derive(Debug)]
+pub struct Unassigned<S> {
+    streams: slab::Slab<StreamData<S>>,
+}
+
+impl<S> Default for Unassigned<S> {
+    fn default() -> Self {
+        Self {
+            streams: slab::Slab::new(),
+        }
+    }
+}
+
+impl<S: backend::Stream> Unassigned<S> {
+    /// Returns a mutable reference and the index where it was inserted
+    pub fn attach_stream(&mut self, stream: S) -> (&mut S, usize) {
+        let index = self.streams.insert(StreamData::new(stream));
+        (&mut self.streams[index].stream, index)
+    }
+
+    pub fn contains(&self, id: backend::StreamId) -> bool {
+        self.streams.iter().any(|(_, s)| s.stream.id() == id)
+    }
+
+    pub fn try_drop_stream(&mut self, id: backend::StreamId) {
+        self.streams
+            .retain(|_, stream_data| stream_data.stream.id() != id);
+    }
+
+    pub fn update_peak(&mut self, index: usize) {
+        if let Some(stream_data) = self.streams.get_mut(index) {
+            match stream_data.stream.get_recent_peak() {
+                Ok(Some(peak)) => stream_data.last_peak = peak,
+                Err(_) => stream_data.last_peak = 0.0,
+                _ => (),
+            }
+        }
+    }
+
+    /// The stream with the highest recent peak, unless all of them are silent.
+    pub fn loudest(&self) -> Option<&S> {
+        self.streams
+            .iter()
+            .map(|(_, s)| s)
+            .filter(|s| s.last_peak > SILENCE)
+            .max_by(|a, b| {
+                a.last_peak
+                    .partial_cmp(&b.last_peak)
+                    .expect("wrong peak information")
+            })
+            .map(|s| &s.stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, Event, Stream, StreamId, StreamInfo};
+    use crate::mock::{MockBackend, MockStream, Operation};
+
+    fn channel(config: &str) -> Channel<MockStream> {
+        let config: config::Channel = toml::from_str(config).unwrap();
+        Channel::new(Some(&config), config::Meter::default())
+    }
+
+    /// Let the mock backend create a stream for a sink-input, like the PulseAudio backend would.
+    fn request_stream(
+        backend: &mut MockBackend,
+        events: &std::sync::mpsc::Receiver<Event<MockStream>>,
+        info: backend::SinkInputInfo,
+    ) -> MockStream {
+        backend.request_sink_input_stream(info, common::Channel::App(0));
+        backend.iterate(false).unwrap();
+        match events.try_recv().unwrap() {
+            Event::NewSinkInput(common::Channel::App(0), stream) => stream,
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn match_sink_input_by_regex() {
+        let ch = channel(
+            r#"
+            [[property-matches]]
+            "application.name" = "Fire.*"
+            "#,
+        );
+        let firefox = MockBackend::sink_input(1, &[("application.name", "Firefox")], false);
+        let mpv = MockBackend::sink_input(2, &[("application.name", "mpv")], false);
+        assert!(ch.match_sink_input(&firefox).is_some());
+        assert!(ch.match_sink_input(&mpv).is_none());
+    }
+
+    #[test]
+    fn best_match_honors_exclusions_and_priorities() {
+        let channels = [
+            channel(
+                r#"
+                [[property-matches]]
+                "application.name" = "Firefox"
+
+                [property-matches.exclude]
+                "media.role" = "phone"
+                "#,
+            ),
+            channel(
+                r#"
+                [[property-matches]]
+                "media.role" = "phone"
+
+                [[property-matches]]
+                "application.name" = "Firefox"
+                "media.name" = "Meet.*"
+                priority = 10
+                "#,
+            ),
+            channel("catch-all = true"),
+        ];
+        let find = |properties: &[(&str, &str)]| {
+            let info = MockBackend::sink_input(1, properties, false);
+            find_best_match(&channels, |c| c.match_sink_input(&info))
+        };
+
+        assert_eq!(find(&[("application.name", "Firefox")]), Some(0));
+        assert_eq!(
+            find(&[("application.name", "Firefox"), ("media.role", "phone")]),
+            Some(1)
+        );
+        assert_eq!(
+            find(&[
+                ("application.name", "Firefox"),
+                ("media.name", "Meet - Foo")
+            ]),
+            Some(1)
+        );
+        assert_eq!(find(&[("application.name", "mpv")]), Some(2));
+    }
+
+    #[test]
+    fn attach_stream_applies_channel_state() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut ch = channel("");
+
+        // The first stream defines the mute state of the channel...
+        let first = MockBackend::sink_input(1, &[], true);
+        let first = request_stream(&mut backend, &events, first);
+        let (_, _, state) = ch.attach_stream(&mut backend, first);
+        assert_eq!(state, common::ChannelState::Muted);
+
+        // ...and all further streams are forced to the channel's mute state and volume.
+        ch.update_volume(&mut backend, 0.5);
+        let second = MockBackend::sink_input(2, &[], false);
+        let second = request_stream(&mut backend, &events, second);
+        let (stream, _, _) = ch.attach_stream(&mut backend, second);
+        assert!(stream.is_mute());
+        match stream.info() {
+            StreamInfo::SinkInput(info) => assert_eq!(info.volume, vec![0.5, 0.5]),
+            info => panic!("unexpected stream {:?}", info),
+        }
+        assert_eq!(
+            backend.operations,
+            vec![
+                Operation::SetVolume(StreamId::SinkInput(1), vec![0.5, 0.5]),
+                Operation::SetMute(StreamId::SinkInput(2), true),
+                Operation::SetVolume(StreamId::SinkInput(2), vec![0.5, 0.5]),
+            ]
+        );
+
+        assert_eq!(
+            ch.try_drop_stream(StreamId::SinkInput(1)),
+            common::ChannelState::Muted
+        );
+        assert_eq!(
+            ch.try_drop_stream(StreamId::SinkInput(2)),
+            common::ChannelState::Inactive
+        );
+    }
+
+    #[test]
+    fn update_volume_keeps_balance() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut balanced = channel("");
+        let mut mono = channel("force-mono = true");
+
+        for (index, ch) in [(1, &mut balanced), (2, &mut mono)].iter_mut() {
+            let mut info = MockBackend::sink_input(*index, &[], false);
+            info.volume = vec![0.5, 1.0];
+            let stream = request_stream(&mut backend, &events, info);
+            ch.attach_stream(&mut backend, stream);
+            ch.update_volume(&mut backend, 0.5);
+        }
+
+        assert_eq!(
+            backend.operations,
+            vec![
+                Operation::SetVolume(StreamId::SinkInput(1), vec![0.25, 0.5]),
+                Operation::SetVolume(StreamId::SinkInput(2), vec![0.5, 0.5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn update_volume_keeps_balance_through_zero() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut ch = channel("");
+
+        let mut info = MockBackend::sink_input(1, &[], false);
+        info.volume = vec![0.5, 1.0];
+        let stream = request_stream(&mut backend, &events, info);
+        ch.attach_stream(&mut backend, stream);
+        ch.update_volume(&mut backend, 0.0);
+        ch.update_volume(&mut backend, 0.8);
+
+        assert_eq!(
+            backend.operations,
+            vec![
+                Operation::SetVolume(StreamId::SinkInput(1), vec![0.0, 0.0]),
+                Operation::SetVolume(StreamId::SinkInput(1), vec![0.4, 0.8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn update_volume_applies_curve() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut ch = channel(
+            r#"
+            volume-curve = { db = -60.0 }
+            max-volume = 1.5
+            "#,
+        );
+        let info = MockBackend::sink_input(1, &[], false);
+        let stream = request_stream(&mut backend, &events, info);
+        ch.attach_stream(&mut backend, stream);
+
+        for fader in [0.0, 0.5, 1.0].iter() {
+            ch.update_volume(&mut backend, *fader);
+        }
+
+        // -30 dB is ~3.16% amplitude, which is ~31.6% as a PulseAudio volume
+        let half = 10.0f32.powf(-30.0 / 20.0).cbrt() * 1.5;
+        assert_eq!(
+            backend.operations,
+            vec![
+                Operation::SetVolume(StreamId::SinkInput(1), vec![0.0, 0.0]),
+                Operation::SetVolume(StreamId::SinkInput(1), vec![half, half]),
+                Operation::SetVolume(StreamId::SinkInput(1), vec![1.5, 1.5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn pickup_waits_for_fader_to_cross_volume() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut ch = channel("pickup = true");
+
+        let info = MockBackend::sink_input(1, &[], false);
+        let stream = request_stream(&mut backend, &events, info);
+        ch.attach_stream(&mut backend, stream);
+        assert_eq!(ch.take_sync_change(), Some(true));
+
+        // the volume is changed elsewhere...
+        ch.sync_volume(StreamId::SinkInput(1), &[0.4, 0.4]);
+        assert_eq!(ch.take_sync_change(), None);
+
+        // ...so the fader is ignored until it crosses it
+        ch.update_volume(&mut backend, 0.2);
+        assert!(backend.operations.is_empty());
+        ch.update_volume(&mut backend, 0.6);
+        assert_eq!(ch.take_sync_change(), Some(false));
+        assert_eq!(
+            backend.operations,
+            vec![Operation::SetVolume(StreamId::SinkInput(1), vec![0.6, 0.6])]
+        );
+    }
+
+    #[test]
+    fn external_sink_changes_are_synced() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut ch = channel(r#"sink = "speakers""#);
+
+        let info = backend::SinkInfo {
+            index: 1,
+            name: Some("speakers".to_owned()),
+            monitoring_source: 2,
+            volume: vec![1.0, 1.0],
+            mute: false,
+        };
+        backend
+            .request_sink_stream(info, common::Channel::App(0))
+            .unwrap();
+        backend.iterate(false).unwrap();
+        let stream = match events.try_recv().unwrap() {
+            Event::NewSink(_, stream) => stream,
+            e => panic!("unexpected event {:?}", e),
+        };
+        ch.attach_stream(&mut backend, stream);
+
+        ch.sync_volume(StreamId::Sink(1), &[0.3, 0.3]);
+        assert_eq!(ch.actual_volume(), Some(0.3));
+        assert_eq!(
+            ch.sync_mute(StreamId::Sink(1), true),
+            Some(common::ChannelState::Muted)
+        );
+        assert_eq!(ch.sync_mute(StreamId::Sink(1), true), None);
+        assert_eq!(ch.sync_mute(StreamId::Sink(2), false), None);
+        assert!(backend.operations.is_empty());
+    }
+
+    #[test]
+    fn update_peak_reports_loudest_stream() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut ch = channel("");
+
+        for index in 1..=2 {
+            let info = MockBackend::sink_input(index, &[], false);
+            let stream = request_stream(&mut backend, &events, info);
+            let (stream, i, _) = ch.attach_stream(&mut backend, stream);
+            stream.set_connected_channel(common::Channel::App(0), i);
+            stream.connect().unwrap();
+        }
+
+        backend.push_peak(StreamId::SinkInput(1), 0.25);
+        backend.push_peak(StreamId::SinkInput(2), 0.75);
+        backend.push_peak(StreamId::SinkInput(2), 0.5);
+
+        let mut peak = 0.0;
+        for event in events.try_iter() {
+            match event {
+                Event::NewPeakData(common::Channel::App(0), index) => {
+                    peak = ch.update_peak(index).unwrap()
+                }
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+        assert_eq!(peak, 0.75);
+    }
+
+    #[test]
+    fn loudest_unassigned_stream_ignores_silence() {
+        let mut backend = MockBackend::new();
+        let events = backend.take_event_receiver().unwrap();
+        let mut unassigned = Unassigned::default();
+
+        for index in 1..=3 {
+            let info = MockBackend::sink_input(index, &[], false);
+            let stream = request_stream(&mut backend, &events, info);
+            let (stream, i) = unassigned.attach_stream(stream);
+            stream.set_connected_channel(UNASSIGNED, i);
+            stream.connect().unwrap();
+        }
+        assert!(unassigned.loudest().is_none());
+
+        backend.push_peak(StreamId::SinkInput(1), 0.005);
+        backend.push_peak(StreamId::SinkInput(2), 0.5);
+        backend.push_peak(StreamId::SinkInput(3), 0.25);
+        for event in events.try_iter() {
+            match event {
+                Event::NewPeakData(UNASSIGNED, index) => unassigned.update_peak(index),
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+        assert_eq!(
+            unassigned.loudest().map(|s| s.id()),
+            Some(StreamId::SinkInput(2))
+        );
+
+        // learning is no change of volume or mute state
+        assert!(backend.operations.is_empty());
+        unassigned.try_drop_stream(StreamId::SinkInput(2));
+        assert!(!unassigned.contains(StreamId::SinkInput(2)));
+        assert_eq!(
+            unassigned.loudest().map(|s| s.id()),
+            Some(StreamId::SinkInput(3))
+        );
+    }
+}
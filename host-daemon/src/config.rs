@@ -1,47 +1,750 @@
+use anyhow::Context;
 use std::collections;
+use std::path;
 use std::rc::Rc;
+use std::time;
 
-pub type PropertyMatches = Rc<Vec<collections::BTreeMap<String, String>>>;
+const APP_NAME: &str = "pavu-mixer";
+const CONFIG_NAME: &str = "pavu-mixer";
 
-#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Name of the profile made up of the top-level channels.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Channels a profile can have at most.  The protocol numbers channels with a byte and the last
+/// numbers are taken by streams which no channel claims.
+const MAX_CHANNELS: usize = u8::MAX as usize - 1;
+
+/// How often the configuration file is checked for changes.
+const WATCH_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+pub type PropertyMatches = Rc<Vec<MatchSet>>;
+
+pub fn file_path() -> anyhow::Result<path::PathBuf> {
+    confy::get_configuration_file_path(APP_NAME, Some(CONFIG_NAME))
+        .context("failed finding configuration file")
+}
+
+/// Load and validate the configuration file, creating it with the defaults if it does not exist
+/// yet.
+pub fn load() -> anyhow::Result<Config> {
+    let path = file_path()?;
+    if !path.exists() {
+        confy::store_path(&path, Config::default()).context("failed storing configuration")?;
+    }
+    let mut source =
+        std::fs::read_to_string(&path).with_context(|| format!("failed reading {:?}", path))?;
+    if let Some(migrated) = migrate_channels(&source)? {
+        log::info!(
+            "Moving the channels in {:?} to the `[[channels]]` list.",
+            path
+        );
+        std::fs::write(&path, &migrated).with_context(|| format!("failed writing {:?}", path))?;
+        source = migrated;
+    }
+    parse(&source).with_context(|| format!("invalid configuration {:?}", path))
+}
+
+/// Validate the configuration file without creating or migrating it.
+pub fn check() -> anyhow::Result<()> {
+    let path = file_path()?;
+    anyhow::ensure!(
+        path.exists(),
+        "there is no configuration file at {:?}",
+        path
+    );
+    let source =
+        std::fs::read_to_string(&path).with_context(|| format!("failed reading {:?}", path))?;
+    parse(&source).with_context(|| format!("invalid configuration {:?}", path))?;
+    Ok(())
+}
+
+/// Convert the fixed `[channel-1]` to `[channel-4]` tables of old configuration files into the
+/// `[[channels]]` list, keeping comments and formatting.
+///
+/// Returns `None` if the configuration does not use the old layout.
+fn migrate_channels(source: &str) -> anyhow::Result<Option<String>> {
+    let mut document: toml_edit::DocumentMut = source.parse()?;
+    let old_keys: Vec<String> = (1..)
+        .map(|i| format!("channel-{}", i))
+        .take_while(|key| document.contains_key(key))
+        .collect();
+    if old_keys.is_empty() {
+        return Ok(None);
+    }
+    anyhow::ensure!(
+        !document.contains_key("channels"),
+        "`channels` cannot be combined with the old `channel-1` to `channel-4` tables"
+    );
+
+    let mut channels = toml_edit::ArrayOfTables::new();
+    for key in old_keys {
+        let mut channel = document
+            .remove(&key)
+            .expect("key was just found")
+            .into_table()
+            .map_err(|_| anyhow::anyhow!("`{}` is not a table", key))?;
+        if channel.is_implicit() {
+            // only had sub-tables like `[[channel-2.property-matches]]` before
+            channel.set_implicit(false);
+            channel.decor_mut().set_prefix("\n");
+        }
+        channels.push(channel);
+    }
+    document.insert("channels", toml_edit::Item::ArrayOfTables(channels));
+    Ok(Some(document.to_string()))
+}
+
+/// Parse and validate a configuration file.
+///
+/// Besides syntax errors, bad regexes and unknown keys, this rejects channels which are assigned
+/// the same sink, source or property matches as another channel.
+pub fn parse(source: &str) -> anyhow::Result<Config> {
+    if let Some(migrated) = migrate_channels(source)? {
+        log::warn!("`channel-1` to `channel-4` are deprecated, use a `[[channels]]` list instead");
+        return parse(&migrated);
+    }
+    let config: Config = toml::from_str(source)?;
+
+    let spanned: SpannedConfig = toml::from_str(source)?;
+    let line = |span: std::ops::Range<usize>| source[..span.start].matches('\n').count() + 1;
+
+    let mut problems = Vec::new();
+    check_duplicates(&spanned.channels, "", &line, &mut problems);
+    for (name, profile) in spanned.profiles.iter() {
+        if name.get_ref() == DEFAULT_PROFILE {
+            problems.push(format!(
+                "line {}: the profile name {:?} is reserved for the top-level channels",
+                line(name.span()),
+                DEFAULT_PROFILE,
+            ));
+        }
+        let prefix = format!("profile {:?}: ", name.get_ref());
+        check_duplicates(&profile.channels, &prefix, &line, &mut problems);
+    }
+
+    for name in config.profile_names() {
+        let count = config.profile_channels(name).map_or(0, <[_]>::len);
+        if count > MAX_CHANNELS {
+            problems.push(format!(
+                "profile {:?} has {} channels, at most {} are supported",
+                name, count, MAX_CHANNELS
+            ));
+        }
+    }
+
+    let channel_meters = config
+        .profile_names()
+        .filter_map(|name| config.profile_channels(name))
+        .flatten()
+        .filter_map(|channel| channel.meter.as_ref());
+    if config.connection.peak_rate <= 0.0 {
+        problems.push(format!(
+            "peak-rate {} must be greater than 0",
+            config.connection.peak_rate
+        ));
+    }
+    for meter in std::iter::once(&config.meter).chain(channel_meters) {
+        if meter.range.0 >= meter.range.1 {
+            problems.push(format!(
+                "meter range {:?} must go from the bottom to the top level",
+                meter.range
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(config)
+    } else {
+        anyhow::bail!("{}", problems.join("\n"))
+    }
+}
+
+/// Add a property match set to a channel of a profile in the configuration file at `path`.
+///
+/// The file is edited in place so comments and formatting survive.  The values are matched
+/// exactly.  The change is picked up by the [`Watcher`] like any other edit.
+pub fn add_match_set(
+    path: &path::Path,
+    profile: &str,
+    index: usize,
+    properties: &crate::backend::Properties,
+) -> anyhow::Result<()> {
+    let source =
+        std::fs::read_to_string(path).with_context(|| format!("failed reading {:?}", path))?;
+    let source = with_match_set(&source, profile, index, properties)?;
+    parse(&source).context("configuration would become invalid")?;
+    std::fs::write(path, source).with_context(|| format!("failed writing {:?}", path))
+}
+
+fn with_match_set(
+    source: &str,
+    profile: &str,
+    index: usize,
+    properties: &crate::backend::Properties,
+) -> anyhow::Result<String> {
+    let mut document: toml_edit::DocumentMut = source.parse()?;
+    let channels = if profile == DEFAULT_PROFILE {
+        document.get_mut("channels")
+    } else {
+        document
+            .get_mut("profiles")
+            .and_then(|profiles| profiles.as_table_like_mut()?.get_mut(profile))
+            .and_then(|profile| profile.as_table_like_mut()?.get_mut("channels"))
+    };
+    let channel = channels
+        .and_then(|channels| channels.get_mut(index))
+        .with_context(|| format!("profile {:?} has no channel {}", profile, index + 1))?;
+    let inline = channel.is_value();
+    let channel = channel
+        .as_table_like_mut()
+        .with_context(|| format!("channel {} is not a table", index + 1))?;
+
+    let set = exact_match_set(properties);
+    match channel.get_mut("property-matches") {
+        Some(toml_edit::Item::ArrayOfTables(sets)) => sets.push(set.into_table()),
+        Some(toml_edit::Item::Value(toml_edit::Value::Array(sets))) => sets.push(set),
+        Some(_) => anyhow::bail!("property-matches of channel {} is not a list", index + 1),
+        None if inline => {
+            let sets: toml_edit::Array = std::iter::once(set).collect();
+            channel.insert("property-matches", toml_edit::value(sets));
+        }
+        None => {
+            let mut sets = toml_edit::ArrayOfTables::new();
+            sets.push(set.into_table());
+            channel.insert("property-matches", toml_edit::Item::ArrayOfTables(sets));
+        }
+    }
+    Ok(document.to_string())
+}
+
+/// A match set matching exactly the given property values.
+pub fn exact_match_set(properties: &crate::backend::Properties) -> toml_edit::InlineTable {
+    properties
+        .iter()
+        .map(|(name, value)| {
+            let pattern = format!("^{}$", regex::escape(value));
+            (name.as_str(), toml_edit::Value::from(pattern))
+        })
+        .collect()
+}
+
+fn check_duplicates(
+    channels: &[SpannedChannel],
+    prefix: &str,
+    line: &dyn Fn(std::ops::Range<usize>) -> usize,
+    problems: &mut Vec<String>,
+) {
+    for (i, channel) in channels.iter().enumerate() {
+        for (j, other) in channels[..i].iter().enumerate() {
+            let (name, other_name) = (i + 1, j + 1);
+            for (what, value, other_value) in [
+                ("sink", &channel.sink, &other.sink),
+                ("source", &channel.source, &other.source),
+            ]
+            .iter()
+            {
+                if let (Some(value), Some(other_value)) = (value, other_value) {
+                    if value.get_ref() == other_value.get_ref() {
+                        problems.push(format!(
+                            "line {}: {}{} {:?} of channel {} is already assigned to channel {} (line {})",
+                            line(value.span()),
+                            prefix,
+                            what,
+                            value.get_ref(),
+                            name,
+                            other_name,
+                            line(other_value.span()),
+                        ));
+                    }
+                }
+            }
+
+            if channel.direction != other.direction {
+                continue;
+            }
+            for set in channel.property_matches.iter() {
+                if let Some(other_set) = other
+                    .property_matches
+                    .iter()
+                    .find(|other_set| other_set.get_ref() == set.get_ref())
+                {
+                    problems.push(format!(
+                        "line {}: {}property matches of channel {} are already assigned to channel {} (line {})",
+                        line(set.span()),
+                        prefix,
+                        name,
+                        other_name,
+                        line(other_set.span()),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// The parts of the configuration which are checked for duplicates, with their location.
+#[derive(serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
+struct SpannedConfig {
+    channels: Vec<SpannedChannel>,
+    #[serde(default)]
+    profiles: collections::BTreeMap<toml::Spanned<String>, SpannedProfile>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SpannedProfile {
+    channels: Vec<SpannedChannel>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SpannedChannel {
+    #[serde(default)]
+    property_matches: Vec<toml::Spanned<MatchSet>>,
+    #[serde(default)]
+    direction: Direction,
+    source: Option<toml::Spanned<String>>,
+    sink: Option<toml::Spanned<String>>,
+}
+
+/// Watches the configuration file for changes.
+pub struct Watcher {
+    path: path::PathBuf,
+    modified: Option<time::SystemTime>,
+    last_check: time::Instant,
+}
+
+impl Watcher {
+    pub fn new() -> anyhow::Result<Self> {
+        let path = file_path()?;
+        Ok(Self {
+            modified: Self::modified(&path),
+            path,
+            last_check: time::Instant::now(),
+        })
+    }
+
+    fn modified(path: &path::Path) -> Option<time::SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Check whether the configuration file changed and load it again if it did.
+    ///
+    /// Returns `None` if nothing changed and an error if the new file is invalid.
+    pub fn poll(&mut self) -> Option<anyhow::Result<Config>> {
+        if self.last_check.elapsed() < WATCH_INTERVAL {
+            return None;
+        }
+        self.last_check = time::Instant::now();
+
+        let modified = Self::modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        Some(
+            std::fs::read_to_string(&self.path)
+                .with_context(|| format!("failed reading {:?}", self.path))
+                .and_then(|s| parse(&s).with_context(|| format!("invalid {:?}", self.path))),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Config {
     pub connection: Connection,
 
-    pub channel_1: Channel,
-    pub channel_2: Channel,
-    pub channel_3: Channel,
-    pub channel_4: Channel,
+    #[serde(default)]
+    pub backend: Backend,
+
+    /// The application channels, from left to right.  There should be as many as the mixer has.
+    pub channels: Vec<Channel>,
 
     pub icon_mappings: Vec<IconMapping>,
 
+    /// Level meter settings of the main channel and the default for all other channels.
+    #[serde(default)]
+    pub meter: Meter,
+
+    /// What the gestures on the main channel's mute button do.
+    #[serde(default = "Buttons::main")]
+    pub main_buttons: Buttons,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub sink_peak_multiplier: Vec<SinkPeakMultiplier>,
+
+    /// Alternative channel layouts which can be switched to at runtime.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "collections::BTreeMap::is_empty")]
+    pub profiles: collections::BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// Names of all profiles in the order they are cycled through, starting with the default.
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(DEFAULT_PROFILE).chain(self.profiles.keys().map(String::as_str))
+    }
+
+    /// The profile after `current`, wrapping around at the end.
+    pub fn next_profile(&self, current: &str) -> &str {
+        let names: Vec<&str> = self.profile_names().collect();
+        let index = names.iter().position(|name| *name == current).unwrap_or(0);
+        names[(index + 1) % names.len()]
+    }
+
+    /// The channels of a profile or `None` if there is no profile with this name.
+    pub fn profile_channels(&self, name: &str) -> Option<&[Channel]> {
+        if name == DEFAULT_PROFILE {
+            Some(&self.channels)
+        } else {
+            self.profiles.get(name).map(|p| &p.channels[..])
+        }
+    }
+
+    /// The icon mappings to use with a profile.
+    ///
+    /// The profile's own mappings take precedence over the top-level ones.
+    pub fn profile_icon_mappings(&self, name: &str) -> Vec<IconMapping> {
+        self.profiles
+            .get(name)
+            .into_iter()
+            .flat_map(|p| p.icon_mappings.iter())
+            .chain(self.icon_mappings.iter())
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+    pub channels: Vec<Channel>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub icon_mappings: Vec<IconMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Connection {
     /// Use `sudo chmod` to make the device accessible instead of proper udev
     pub sudo_hack: bool,
+
+    /// How often per second the meter levels of all channels are sent to the mixer.
+    #[serde(default = "default_peak_rate")]
+    pub peak_rate: f32,
 }
 
-#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+fn default_peak_rate() -> f32 {
+    30.0
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Backend {
+    /// Which audio server to talk to.
+    #[serde(default)]
+    pub kind: BackendKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    /// PulseAudio, or PipeWire through `pipewire-pulse`.
+    #[default]
+    Pulseaudio,
+    /// Native PipeWire API (requires the `pipewire` feature).
+    Pipewire,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Channel {
+    #[serde(default)]
     pub property_matches: PropertyMatches,
+
+    /// Whether the property matches select playback streams (sink-inputs) or recording streams
+    /// (source-outputs).
+    #[serde(default)]
+    pub direction: Direction,
+
+    /// Attach all streams (of this channel's direction) which no other channel matches.
+    #[serde(default)]
+    pub catch_all: bool,
+
+    /// Name of a PulseAudio source (e.g. a microphone) this channel controls instead of
+    /// application streams.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// Name of a PulseAudio sink (e.g. headphones) this channel controls instead of application
+    /// streams.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sink: Option<String>,
+
+    /// Set all audio channels of a stream to the same volume instead of keeping their balance.
+    #[serde(default)]
+    pub force_mono: bool,
+
+    /// How the fader position maps to the stream volume.
+    #[serde(default)]
+    pub volume_curve: VolumeCurve,
+
+    /// Volume at the top of the fader travel, `1.0` being 100%.  Can be raised above 100% for
+    /// quiet applications.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_volume: Option<f32>,
+
+    /// Soft-takeover: after the volume was changed elsewhere, ignore the fader until it crosses
+    /// the actual volume instead of jumping to the fader position.
+    #[serde(default)]
+    pub pickup: bool,
+
+    /// Level meter settings for this channel, instead of the top-level ones.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meter: Option<Meter>,
+
+    /// Text shown below the icon on the display, instead of the application's name.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+
+    /// What the gestures on the channel's mute button do.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Buttons::is_default")]
+    pub buttons: Buttons,
 }
 
-#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// Actions for the gestures of a mute button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct Buttons {
+    pub click: ButtonAction,
+    pub double_press: ButtonAction,
+    pub long_press: ButtonAction,
+}
+
+impl Default for Buttons {
+    fn default() -> Self {
+        Self {
+            click: ButtonAction::Mute,
+            double_press: ButtonAction::None,
+            long_press: ButtonAction::Learn,
+        }
+    }
+}
+
+impl Buttons {
+    /// The defaults of the main channel, which has nothing to learn but switches profiles.
+    pub fn main() -> Self {
+        Self {
+            click: ButtonAction::Mute,
+            double_press: ButtonAction::NextProfile,
+            long_press: ButtonAction::None,
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the button has to tell double presses from clicks, which delays the clicks.
+    pub fn uses_double_press(&self) -> bool {
+        self.double_press != ButtonAction::None
+    }
+
+    /// Whether any of the gestures learns streams for the channel.
+    pub fn learns(&self) -> bool {
+        [self.click, self.double_press, self.long_press].contains(&ButtonAction::Learn)
+    }
+
+    /// The action for a gesture, `None` for the plain press and release events.
+    pub fn action(&self, event: common::ButtonEvent) -> Option<ButtonAction> {
+        match event {
+            common::ButtonEvent::Click => Some(self.click),
+            common::ButtonEvent::DoublePress => Some(self.double_press),
+            common::ButtonEvent::LongPress => Some(self.long_press),
+            common::ButtonEvent::Pressed | common::ButtonEvent::Released => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ButtonAction {
+    /// Do nothing.
+    None,
+    /// Toggle the mute state of the channel.
+    Mute,
+    /// Mute all other channels, or unmute them again if the channel is soloed already.
+    Solo,
+    /// Pause or resume the channel's application through its MPRIS interface (using
+    /// `playerctl`).
+    PlayPause,
+    /// Make the next sink the default one.
+    CycleOutput,
+    /// Switch to the next profile.
+    NextProfile,
+    /// Assign the loudest stream no channel claims to the channel.
+    Learn,
+}
+
+/// A set of property matches.  All properties have to match (verbatim or as a regex) for the set
+/// to match a stream.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
+pub struct MatchSet {
+    /// When a stream is matched by multiple channels, the set with the highest priority wins.
+    /// Defaults to 0, ties go to the lower channel.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+
+    /// The set does not match if any of these properties match.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "collections::BTreeMap::is_empty")]
+    pub exclude: collections::BTreeMap<String, Pattern>,
+
+    #[serde(flatten)]
+    pub properties: collections::BTreeMap<String, Pattern>,
+}
+
+/// A property value to match, either verbatim or as a regular expression.
+///
+/// The regex is compiled when the configuration is loaded so invalid ones are reported right
+/// away.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: regex::Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            source: source.to_owned(),
+            regex: regex::Regex::new(source)?,
+        })
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        value == self.source || self.regex.is_match(value)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl serde::Serialize for Pattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Pattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::new(&source)
+            .map_err(|e| serde::de::Error::custom(format!("invalid regex {:?}:\n{}", source, e)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    #[default]
+    Playback,
+    Recording,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VolumeCurve {
+    /// The fader position is the linear amplitude.
+    Linear,
+    /// The fader position is the PulseAudio volume, just like the sliders in `pavucontrol`.
+    #[default]
+    Cubic,
+    /// The fader spans the given dB range (e.g. `-60.0`) up to 0 dB, with its bottom end
+    /// muting the stream entirely.
+    Db(f32),
+}
+
+/// Calibration and ballistics of a level meter.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct Meter {
+    /// Peak levels in dBFS shown at the bottom and the top of the meter.
+    pub range: (f32, f32),
+    /// Time constant for rising levels, in milliseconds.  `0` follows peaks instantly.
+    pub attack: f32,
+    /// Time constant for falling levels, in milliseconds.
+    pub release: f32,
+    /// How long the highest level stays shown, in milliseconds.  `0` disables peak-hold.
+    pub peak_hold: f32,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self {
+            range: (-60.0, 0.0),
+            attack: 0.0,
+            release: 300.0,
+            peak_hold: 1000.0,
+        }
+    }
+}
+
+impl Meter {
+    /// Map a linear peak amplitude to a meter position (`0.0` to `1.0`).
+    pub fn position_for_peak(&self, peak: f32) -> f32 {
+        let (bottom, top) = self.range;
+        if peak <= 0.0 {
+            return 0.0;
+        }
+        let db = 20.0 * peak.log10();
+        ((db - bottom) / (top - bottom)).clamp(0.0, 1.0)
+    }
+}
+
+impl VolumeCurve {
+    /// Map a fader position (`0.0` to `1.0`) to a volume, `1.0` being 100%.
+    pub fn volume_for_fader(self, fader: f32) -> f32 {
+        let fader = fader.clamp(0.0, 1.0);
+        match self {
+            // PulseAudio volumes are cubic, see pa_sw_volume_from_linear()
+            VolumeCurve::Linear => fader.cbrt(),
+            VolumeCurve::Cubic => fader,
+            VolumeCurve::Db(_) if fader == 0.0 => 0.0,
+            VolumeCurve::Db(range) => {
+                let db = -range.abs() * (1.0 - fader);
+                10.0f32.powf(db / 20.0).cbrt()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct IconMapping {
     pub icon: String,
-    pub property_matches: collections::BTreeMap<String, String>,
+    pub property_matches: collections::BTreeMap<String, Pattern>,
 }
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SinkPeakMultiplier {
     pub sink_name: String,
     pub multiplier: f32,
@@ -52,3 +755,176 @@ impl Default for Config {
         toml::de::from_str(include_str!("default-config.toml")).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_source() -> &'static str {
+        include_str!("default-config.toml")
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        parse(default_source()).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_regex_and_unknown_keys() {
+        let bad_regex = format!(
+            "{}\n[[channels]]\n[[channels.property-matches]]\n\"application.name\" = \"(\"",
+            default_source()
+        );
+        let err = format!("{:#}", parse(&bad_regex).unwrap_err());
+        assert!(err.contains("invalid regex"), "{}", err);
+
+        let unknown_key = default_source().replace("[backend]", "[backend]\nkinf = \"pipewire\"");
+        let err = format!("{:#}", parse(&unknown_key).unwrap_err());
+        assert!(err.contains("unknown field `kinf`"), "{}", err);
+    }
+
+    #[test]
+    fn profiles_are_checked_separately() {
+        let source = format!(
+            "{}\n[[profiles.music.channels]]\nsink = \"speakers\"\n\n\
+             [[profiles.music.channels]]\nsink = \"speakers\"",
+            default_source()
+        );
+        let err = format!("{:#}", parse(&source).unwrap_err());
+        assert!(err.contains("profile \"music\": sink"), "{}", err);
+
+        let source = format!(
+            "{}\n[[profiles.default.channels]]\nsink = \"speakers\"",
+            default_source()
+        );
+        let err = format!("{:#}", parse(&source).unwrap_err());
+        assert!(err.contains("reserved"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_too_many_channels() {
+        let source = format!(
+            "icon-mappings = []\n[connection]\nsudo-hack = false\n{}",
+            "\n[[channels]]".repeat(MAX_CHANNELS)
+        );
+        assert_eq!(parse(&source).unwrap().channels.len(), MAX_CHANNELS);
+
+        let source = format!(
+            "{}{}",
+            source,
+            "\n[[profiles.many.channels]]".repeat(MAX_CHANNELS + 1)
+        );
+        let err = format!("{:#}", parse(&source).unwrap_err());
+        assert!(err.contains("profile \"many\" has 255 channels"), "{}", err);
+    }
+
+    #[test]
+    fn button_gestures_default_per_channel() {
+        let source = format!(
+            "{}\n[[channels]]\n[channels.buttons]\n\
+             double-press = \"solo\"\nlong-press = \"play-pause\"",
+            default_source()
+        );
+        let config = parse(&source).unwrap();
+        let buttons = config.channels.last().unwrap().buttons;
+        assert_eq!(
+            buttons.action(common::ButtonEvent::Click),
+            Some(ButtonAction::Mute)
+        );
+        assert_eq!(buttons.double_press, ButtonAction::Solo);
+        assert_eq!(buttons.long_press, ButtonAction::PlayPause);
+        assert_eq!(buttons.action(common::ButtonEvent::Pressed), None);
+        assert_eq!(config.channels[0].buttons, Buttons::default());
+        assert_eq!(config.main_buttons.double_press, ButtonAction::NextProfile);
+    }
+
+    #[test]
+    fn rejects_duplicate_sinks() {
+        let source = format!(
+            "{}\n[[channels]]\nsink = \"speakers\"\n\n[[channels]]\nsink = \"speakers\"",
+            default_source()
+        );
+        let err = format!("{:#}", parse(&source).unwrap_err());
+        assert!(
+            err.contains("of channel 6 is already assigned to channel 5"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn old_channel_tables_are_migrated() {
+        let source = r#"
+            icon-mappings = []
+
+            [connection]
+            sudo-hack = false
+
+            # speakers first
+            [channel-1]
+            sink = "speakers"
+
+            [[channel-2.property-matches]]
+            "application.name" = "Firefox"
+
+            [channel-3]
+            force-mono = true
+            [[channel-3.property-matches]]
+            "application.name" = "mpv"
+
+            [channel-4]
+            source = "microphone"
+        "#;
+        let migrated = migrate_channels(source).unwrap().unwrap();
+        assert!(migrated.contains("# speakers first"), "{}", migrated);
+        assert!(!migrated.contains("channel-"), "{}", migrated);
+
+        let config = parse(source).unwrap();
+        assert_eq!(config.channels.len(), 4);
+        assert_eq!(config.channels[0].sink.as_deref(), Some("speakers"));
+        assert!(
+            config.channels[1].property_matches[0].properties["application.name"]
+                .is_match("Firefox")
+        );
+        assert!(config.channels[2].force_mono);
+        assert_eq!(config.channels[2].property_matches.len(), 1);
+        assert_eq!(config.channels[3].source.as_deref(), Some("microphone"));
+
+        assert!(migrate_channels(default_source()).unwrap().is_none());
+        let mixed = format!("{}\n[channel-1]\nsink = \"speakers\"", default_source());
+        let err = format!("{:#}", parse(&mixed).unwrap_err());
+        assert!(err.contains("cannot be combined"), "{}", err);
+    }
+
+    #[test]
+    fn learned_match_sets_are_appended() {
+        let properties: crate::backend::Properties = [
+            ("application.name", "Game (x64)"),
+            ("application.process.binary", "game"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let source = with_match_set(default_source(), DEFAULT_PROFILE, 1, &properties).unwrap();
+        assert!(source.contains("# Either \"pulseaudio\""));
+        let config = parse(&source).unwrap();
+        let learned = &config.channels[1].property_matches[1].properties["application.name"];
+        assert!(learned.is_match("Game (x64)"));
+        assert!(!learned.is_match("Other Game (x64)"));
+
+        let source = format!(
+            "{}\n[[profiles.music.channels]]\nsink = \"speakers\"",
+            default_source()
+        );
+        let source = with_match_set(&source, "music", 0, &properties).unwrap();
+        let config = parse(&source).unwrap();
+        assert_eq!(
+            config.profile_channels("music").unwrap()[0]
+                .property_matches
+                .len(),
+            1
+        );
+        assert!(with_match_set(&source, "music", 1, &properties).is_err());
+    }
+}
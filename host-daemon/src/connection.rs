@@ -1,9 +1,16 @@
 use crate::config;
+use crate::device::{self, Device as _};
 use anyhow::Context;
 use std::sync;
 use std::sync::atomic;
 use std::time;
 
+/// How long the mixer may take to answer the handshake.
+const HANDSHAKE_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+/// How often an icon is sent before giving up on it.
+const ICON_ATTEMPTS: u32 = 3;
+
 /// Error to mark that the USB device disconnected.
 ///
 /// This "error" is handled specially to allow the application to gracefully shutdown in such a
@@ -33,6 +40,18 @@ pub struct PavuMixer {
     dev_handle: sync::Arc<rusb::DeviceHandle<rusb::GlobalContext>>,
     incoming: sync::mpsc::Receiver<anyhow::Result<common::DeviceMessage>>,
     teardown_flag: sync::Arc<atomic::AtomicBool>,
+    /// What the mixer reported about itself in the handshake.
+    device: common::DeviceInfo,
+    next_sequence: u16,
+    pending_icons: Vec<PendingIcon>,
+}
+
+/// An icon sent to the mixer which it did not confirm yet.
+struct PendingIcon {
+    ch: common::Channel,
+    sequence: u16,
+    data: Vec<u8>,
+    attempts: u32,
 }
 
 struct DeviceInfo {
@@ -109,15 +128,114 @@ impl PavuMixer {
             move || receiver_task(dev_handle, dev_info, tx, teardown_flag)
         });
 
-        Ok(Self {
+        let mut mixer = Self {
             dev_info,
             dev_handle,
             incoming: rx,
             teardown_flag,
-        })
+            // filled in by the handshake right below
+            device: Default::default(),
+            next_sequence: 0,
+            pending_icons: Vec::new(),
+        };
+        mixer.device = mixer.handshake()?;
+
+        let device = &mixer.device;
+        let (major, minor, patch) = device.firmware_version;
+        log::info!(
+            "Connected to mixer with firmware {}.{}.{} and {} channels.",
+            major,
+            minor,
+            patch,
+            device.channel_count
+        );
+        if device.protocol_version != common::PROTOCOL_VERSION {
+            anyhow::bail!(
+                "the mixer firmware speaks protocol version {} but the daemon speaks version {}, \
+                 update both to the same release",
+                device.protocol_version,
+                common::PROTOCOL_VERSION
+            );
+        }
+        if !device.capabilities.icons {
+            log::info!("The mixer cannot show icons, not sending any.");
+        }
+        Ok(mixer)
     }
 
-    pub fn send(&mut self, msg: common::HostMessage) -> anyhow::Result<()> {
+    /// Exchange `Hello` messages with the mixer to learn about its firmware.
+    fn handshake(&mut self) -> anyhow::Result<common::DeviceInfo> {
+        self.send(common::HostMessage::Hello(common::PROTOCOL_VERSION))?;
+
+        let deadline = time::Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(time::Instant::now());
+            match self.incoming.recv_timeout(remaining) {
+                Ok(Ok(common::DeviceMessage::Hello(device))) => return Ok(device),
+                Ok(Ok(message)) => log::debug!("Dropping message before handshake: {:?}", message),
+                Ok(Err(e)) if e.downcast_ref::<DeviceDisconnectedError>().is_some() => {
+                    return Err(e)
+                }
+                // most likely left over from a daemon speaking another protocol version
+                Ok(Err(e)) => log::debug!("Dropping message before handshake: {:#}", e),
+                Err(sync::mpsc::RecvTimeoutError::Timeout) => anyhow::bail!(
+                    "the mixer did not answer the handshake, its firmware is probably older \
+                     than the daemon"
+                ),
+                Err(e) => return Err(e).context("failed receiving from channel"),
+            }
+        }
+    }
+
+    fn transmit_icon(
+        &mut self,
+        ch: common::Channel,
+        data: Vec<u8>,
+        attempts: u32,
+    ) -> anyhow::Result<()> {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let header = common::IconHeader {
+            channel: ch,
+            sequence,
+            length: data.len() as u32,
+            crc: common::Crc32::checksum(&data),
+        };
+        let mut frame = Vec::with_capacity(common::IconHeader::SIZE + data.len());
+        frame.extend_from_slice(&header.to_bytes());
+        frame.extend_from_slice(&data);
+        self.send_bulk(&frame)?;
+
+        self.pending_icons.push(PendingIcon {
+            ch,
+            sequence,
+            data,
+            attempts,
+        });
+        Ok(())
+    }
+
+    fn send_bulk(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        log::trace!("sending bulk: {} bytes", buf.len());
+
+        self.dev_handle
+            .write_bulk(
+                self.dev_info.ep.bulk_address,
+                buf,
+                std::time::Duration::from_secs(5),
+            )
+            .map_err(interpret_usb_error)?;
+
+        Ok(())
+    }
+}
+
+impl device::Device for PavuMixer {
+    fn info(&self) -> &common::DeviceInfo {
+        &self.device
+    }
+
+    fn send(&mut self, msg: common::HostMessage) -> anyhow::Result<()> {
         log::trace!("sending: {:?}", msg);
 
         // for now we know that the ep can only take 64 bytes
@@ -135,7 +253,7 @@ impl PavuMixer {
         Ok(())
     }
 
-    pub fn try_recv(&mut self) -> anyhow::Result<Option<common::DeviceMessage>> {
+    fn try_recv(&mut self) -> anyhow::Result<Option<common::DeviceMessage>> {
         match self.incoming.try_recv() {
             Ok(val) => Some(val).transpose(),
             Err(sync::mpsc::TryRecvError::Empty) => Ok(None),
@@ -143,18 +261,64 @@ impl PavuMixer {
         }
     }
 
-    pub fn send_bulk(&mut self, buf: &[u8]) -> anyhow::Result<()> {
-        log::trace!("sending bulk: {} bytes", buf.len());
+    /// Show an icon on a channel, unless the mixer cannot display icons.
+    fn send_icon(&mut self, ch: common::Channel, icon_data: &[u8]) -> anyhow::Result<()> {
+        if !self.device.capabilities.icons {
+            return Ok(());
+        }
+        // an older icon of the channel is superseded, whatever happens to it
+        self.pending_icons.retain(|icon| icon.ch != ch);
+        self.transmit_icon(ch, icon_data.to_vec(), 1)
+    }
 
-        self.dev_handle
-            .write_bulk(
-                self.dev_info.ep.bulk_address,
-                buf,
-                std::time::Duration::from_secs(5),
-            )
-            .map_err(interpret_usb_error)?;
+    /// Show a label below a channel's icon, unless the mixer has no display.
+    fn send_label(&mut self, ch: common::Channel, label: &str) -> anyhow::Result<()> {
+        if !self.device.capabilities.display {
+            return Ok(());
+        }
+        self.send(common::HostMessage::SetLabel(ch, common::make_label(label)))
+    }
 
-        Ok(())
+    /// Handle the mixer's report about an icon transfer, sending the icon again if it failed.
+    fn handle_icon_status(
+        &mut self,
+        sequence: u16,
+        status: common::IconStatus,
+    ) -> anyhow::Result<()> {
+        let index = match self
+            .pending_icons
+            .iter()
+            .position(|icon| icon.sequence == sequence)
+        {
+            Some(index) => index,
+            None => {
+                log::debug!("Ignoring status of superseded icon transfer {}.", sequence);
+                return Ok(());
+            }
+        };
+        let icon = self.pending_icons.remove(index);
+        match status {
+            common::IconStatus::Shown => {
+                log::trace!("Icon for channel {:?} is shown.", icon.ch);
+                Ok(())
+            }
+            status if icon.attempts < ICON_ATTEMPTS => {
+                log::warn!(
+                    "Sending icon for channel {:?} failed ({:?}), retrying...",
+                    icon.ch,
+                    status
+                );
+                self.transmit_icon(icon.ch, icon.data, icon.attempts + 1)
+            }
+            status => {
+                log::warn!(
+                    "Sending icon for channel {:?} failed ({:?}), giving up.",
+                    icon.ch,
+                    status
+                );
+                Ok(())
+            }
+        }
     }
 }
 
@@ -0,0 +1,318 @@
+//! Local control socket for inspecting and steering the daemon at runtime.
+//!
+//! A client connects, writes the command and its arguments (one per line), shuts down its writing
+//! half and then reads the reply until the daemon closes the connection.  Failed commands are
+//! answered with a reply starting with `error: `.  `pavu-mixer-ctl` is the client for this.
+use crate::socket;
+use anyhow::Context;
+use std::io::{self, Read, Write};
+use std::os::unix::net;
+use std::path;
+use std::time;
+
+/// How long a client may take to send its command.
+const READ_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/// How long a client may take to read the reply.
+const WRITE_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/// A channel as named on the command-line: `main` or its number, starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelArg {
+    Main,
+    App(usize),
+}
+
+impl ChannelArg {
+    fn parse(arg: &str) -> Result<Self, String> {
+        if arg == "main" {
+            return Ok(ChannelArg::Main);
+        }
+        match arg.parse::<usize>() {
+            Ok(number) if number > 0 => Ok(ChannelArg::App(number - 1)),
+            _ => Err(format!(
+                "invalid channel {:?}, expected \"main\" or 1, 2, ...",
+                arg
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Show all channels with their attached streams, volume and mute state.
+    Status,
+    /// List all sink-inputs with their properties.
+    Streams,
+    /// Attach a sink-input to a channel, regardless of the property matches.
+    Assign { stream: u32, channel: usize },
+    /// Like `Assign`, but remember the stream's application for the next time it shows up.
+    Pin { stream: u32, channel: usize },
+    /// Forget the pinned channel of a stream's application.
+    Unpin { stream: u32 },
+    /// Toggle the mute state of a channel.
+    Mute(ChannelArg),
+    /// Set the volume of all streams on a channel.
+    Volume(ChannelArg, f32),
+    /// Show the active profile or switch to another one.
+    Profile(Option<String>),
+}
+
+impl Command {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        match args {
+            ["status"] => Ok(Command::Status),
+            ["streams"] => Ok(Command::Streams),
+            [command @ ("assign" | "pin"), stream, channel] => {
+                let stream = parse_stream(stream)?;
+                let channel = match ChannelArg::parse(channel)? {
+                    ChannelArg::App(channel) => channel,
+                    ChannelArg::Main => return Err("streams cannot be assigned to main".to_owned()),
+                };
+                if *command == "pin" {
+                    Ok(Command::Pin { stream, channel })
+                } else {
+                    Ok(Command::Assign { stream, channel })
+                }
+            }
+            ["unpin", stream] => Ok(Command::Unpin {
+                stream: parse_stream(stream)?,
+            }),
+            ["mute", channel] => Ok(Command::Mute(ChannelArg::parse(channel)?)),
+            ["volume", channel, volume] => {
+                let percent = volume
+                    .trim_end_matches('%')
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|percent| (0.0..=150.0).contains(percent))
+                    .ok_or_else(|| format!("invalid volume {:?}, expected 0 to 150 %", volume))?;
+                Ok(Command::Volume(
+                    ChannelArg::parse(channel)?,
+                    percent / 100.0,
+                ))
+            }
+            ["profile"] => Ok(Command::Profile(None)),
+            ["profile", name] => Ok(Command::Profile(Some((*name).to_owned()))),
+            [] => Err("missing command".to_owned()),
+            [command, ..] => Err(format!("invalid command or arguments for {:?}", command)),
+        }
+    }
+}
+
+fn parse_stream(arg: &str) -> Result<u32, String> {
+    arg.parse()
+        .map_err(|_| format!("invalid stream index {:?}", arg))
+}
+
+/// A command received on the control socket, waiting for its reply.
+pub struct Request {
+    pub command: Command,
+    connection: net::UnixStream,
+}
+
+impl Request {
+    pub fn reply(mut self, reply: Result<String, String>) {
+        send_reply(&mut self.connection, reply);
+    }
+}
+
+fn send_reply(connection: &mut net::UnixStream, reply: Result<String, String>) {
+    let text = match reply {
+        Ok(text) => text,
+        Err(e) => format!("error: {}\n", e),
+    };
+    // long replies might not fit into the socket buffer at once
+    let result = connection
+        .set_nonblocking(false)
+        .and_then(|_| connection.set_write_timeout(Some(WRITE_TIMEOUT)))
+        .and_then(|_| connection.write_all(text.as_bytes()));
+    if let Err(e) = result {
+        log::warn!("Failed replying on control socket: {}", e);
+    }
+}
+
+/// A client connection whose command did not arrive completely yet.
+struct PendingConnection {
+    connection: net::UnixStream,
+    text: Vec<u8>,
+    accepted: time::Instant,
+}
+
+impl PendingConnection {
+    /// Read whatever the client sent so far.  Returns whether the command is complete.
+    fn read(&mut self) -> io::Result<bool> {
+        let mut buffer = [0; 256];
+        loop {
+            match self.connection.read(&mut buffer) {
+                Ok(0) => return Ok(true),
+                Ok(n) => self.text.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+pub struct Server {
+    listener: net::UnixListener,
+    path: path::PathBuf,
+    /// Accepted connections which are still sending their command.
+    pending: Vec<PendingConnection>,
+}
+
+impl Server {
+    pub fn bind() -> anyhow::Result<Self> {
+        Self::bind_at(socket::socket_path()?)
+    }
+
+    fn bind_at(path: path::PathBuf) -> anyhow::Result<Self> {
+        if path.exists() {
+            if net::UnixStream::connect(&path).is_ok() {
+                anyhow::bail!("another daemon is already listening on {:?}", path);
+            }
+            // left behind by a daemon which did not exit cleanly
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed removing stale socket {:?}", path))?;
+        }
+
+        let listener = net::UnixListener::bind(&path)
+            .with_context(|| format!("failed binding control socket {:?}", path))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            path,
+            pending: Vec::new(),
+        })
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let connection = match self.listener.accept() {
+                Ok((connection, _)) => connection,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log::warn!("Failed accepting control connection: {}", e);
+                    return;
+                }
+            };
+            // never wait for a client on the main loop, commands are collected over several polls
+            if let Err(e) = connection.set_nonblocking(true) {
+                log::warn!("Failed setting up control connection: {}", e);
+                continue;
+            }
+            self.pending.push(PendingConnection {
+                connection,
+                text: Vec::new(),
+                accepted: time::Instant::now(),
+            });
+        }
+    }
+
+    /// Return the next complete request, if any.
+    ///
+    /// Invalid requests are answered right away.
+    pub fn poll(&mut self) -> Option<Request> {
+        self.accept();
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            match self.pending[i].read() {
+                Ok(true) => (),
+                Ok(false) if self.pending[i].accepted.elapsed() < READ_TIMEOUT => {
+                    i += 1;
+                    continue;
+                }
+                Ok(false) => {
+                    log::warn!("Control client took too long to send its command");
+                    self.pending.swap_remove(i);
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Failed reading from control connection: {}", e);
+                    self.pending.swap_remove(i);
+                    continue;
+                }
+            }
+
+            let PendingConnection {
+                mut connection,
+                text,
+                ..
+            } = self.pending.swap_remove(i);
+            let text = String::from_utf8_lossy(&text);
+            let args: Vec<&str> = text.lines().collect();
+            match Command::parse(&args) {
+                Ok(command) => {
+                    log::debug!("Control command: {:?}", command);
+                    return Some(Request {
+                        command,
+                        connection,
+                    });
+                }
+                Err(e) => send_reply(&mut connection, Err(e)),
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            Command::parse(&["assign", "42", "2"]),
+            Ok(Command::Assign {
+                stream: 42,
+                channel: 1
+            })
+        );
+        assert_eq!(
+            Command::parse(&["volume", "main", "50%"]),
+            Ok(Command::Volume(ChannelArg::Main, 0.5))
+        );
+        assert_eq!(
+            Command::parse(&["pin", "42", "1"]),
+            Ok(Command::Pin {
+                stream: 42,
+                channel: 0
+            })
+        );
+        assert!(Command::parse(&["assign", "42", "main"]).is_err());
+        assert!(Command::parse(&["unpin", "firefox"]).is_err());
+        assert!(Command::parse(&["mute", "0"]).is_err());
+        assert!(Command::parse(&["volume", "1", "loud"]).is_err());
+        assert!(Command::parse(&["status", "now"]).is_err());
+    }
+
+    #[test]
+    fn commands_arrive_over_several_polls() {
+        let path =
+            std::env::temp_dir().join(format!("pavu-mixer-test-{}.sock", std::process::id()));
+        let mut server = Server::bind_at(path.clone()).unwrap();
+
+        let mut client = net::UnixStream::connect(&path).unwrap();
+        client.write_all(b"volume\nmain").unwrap();
+        assert!(server.poll().is_none());
+
+        client.write_all(b"\n50").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let request = server.poll().unwrap();
+        assert_eq!(request.command, Command::Volume(ChannelArg::Main, 0.5));
+        request.reply(Ok("done\n".to_owned()));
+
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "done\n");
+        assert!(server.poll().is_none());
+    }
+}
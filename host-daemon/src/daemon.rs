@@ -0,0 +1,1319 @@
+//! The daemon's state machine, tying the audio server to the mixer.
+//!
+//! [`Daemon`] reacts to backend events, mixer messages and control commands.  It only talks to the
+//! outside world through the [`backend::Backend`] and [`device::Device`] traits, so the whole event
+//! handling can run against mocks in tests.
+use crate::backend::{self, Stream as _};
+use crate::channel;
+use crate::config;
+use crate::control;
+use crate::device;
+use crate::icon;
+use crate::meter;
+use crate::state;
+use anyhow::Context;
+use std::collections;
+use std::path;
+use std::sync::mpsc;
+
+pub struct Daemon<'a, B: backend::Backend, D> {
+    config: &'a mut config::Config,
+    profile: &'a mut String,
+    state: &'a mut state::State,
+    /// The configuration file learned streams are added to.
+    config_path: path::PathBuf,
+    device: D,
+    backend: B,
+    events: mpsc::Receiver<backend::Event<B::Stream>>,
+
+    main: channel::Channel<B::Stream>,
+    /// Streams of sink-inputs no channel claims, monitored for learn mode.
+    unassigned: channel::Unassigned<B::Stream>,
+    channels: Vec<channel::Channel<B::Stream>>,
+    icon_mappings: Vec<config::IconMapping>,
+    peak_frames: meter::Frames,
+    active_sink: Option<String>,
+    /// Names of all sinks by index, to cycle through them.
+    sinks: collections::BTreeMap<u32, String>,
+    /// The channel all others were muted for.
+    solo: Option<usize>,
+    /// All sink-inputs, including those not attached to any channel.
+    sink_inputs: collections::BTreeMap<u32, backend::SinkInputInfo>,
+    /// Sink-inputs assigned to a channel by hand, by sink-input index.
+    assignments: collections::HashMap<u32, usize>,
+    /// The channel each outstanding stream request is for.  Streams arriving for any other channel
+    /// were requested before the stream moved or the channels were rebuilt.
+    requested: collections::HashMap<backend::StreamId, common::Channel>,
+    /// The profile to switch to after the current round of events.
+    next_profile: Option<String>,
+    /// Whether all channels need to be rebuilt after the current round of events.
+    reattach: bool,
+}
+
+impl<'a, B: backend::Backend, D: device::Device> Daemon<'a, B, D> {
+    /// Take over the mixer and put all of its channels into a known state.
+    pub fn new(
+        config: &'a mut config::Config,
+        profile: &'a mut String,
+        state: &'a mut state::State,
+        mut device: D,
+        mut backend: B,
+    ) -> anyhow::Result<Self> {
+        let events = backend
+            .take_event_receiver()
+            .expect("events channel missing");
+        let channels = channels_from_config(config, profile);
+        let icon_mappings = config.profile_icon_mappings(profile);
+
+        // When we start up, there might still be some messages waiting for us - drop them because
+        // we will request up-to-date ones in the next step.
+        while let Some(message) = device.try_recv().context("failed reading from mixer")? {
+            log::debug!("Dropping stale message from device: {:?}", message);
+        }
+
+        check_channel_count(device.info().channel_count, channels.len());
+
+        // Put all channels into known state.
+        for ch in std::iter::once(common::Channel::Main)
+            .chain((0..channels.len()).map(common::Channel::from_index))
+        {
+            device
+                .send(common::HostMessage::UpdateChannelState(
+                    ch,
+                    common::ChannelState::Inactive,
+                ))
+                .context("failed sending message to device")?;
+            let double_press = channel_buttons(config, &channels, ch).uses_double_press();
+            device
+                .send(common::HostMessage::SetDoublePress(ch, double_press))
+                .context("failed sending message to device")?;
+        }
+        // The first frame resets all levels to zero.
+        let peak_frames = meter::Frames::new(
+            config.connection.peak_rate,
+            device.info().channel_count.into(),
+        );
+
+        // Force an update during daemon startup so we'll have up-to-date values for all channels.
+        device.send(common::HostMessage::ForceUpdate)?;
+
+        Ok(Self {
+            main: channel::Channel::new(None, config.meter),
+            unassigned: channel::Unassigned::default(),
+            channels,
+            icon_mappings,
+            peak_frames,
+            active_sink: None,
+            sinks: collections::BTreeMap::new(),
+            solo: None,
+            sink_inputs: collections::BTreeMap::new(),
+            assignments: collections::HashMap::new(),
+            requested: collections::HashMap::new(),
+            next_profile: None,
+            reattach: false,
+
+            config_path: config::file_path()?,
+            config,
+            profile,
+            state,
+            device,
+            backend,
+            events,
+        })
+    }
+
+    /// Let the backend process pending work, optionally blocking until something happened.
+    pub fn iterate(&mut self, block: bool) -> anyhow::Result<()> {
+        self.backend.iterate(block)
+    }
+
+    /// Handle all pending events from the audio server.
+    pub fn handle_events(&mut self) -> anyhow::Result<()> {
+        while let Ok(event) = self.events.try_recv() {
+            self.handle_event(event)?;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: backend::Event<B::Stream>) -> anyhow::Result<()> {
+        match event {
+            backend::Event::NewDefaultSink(stream) => {
+                self.main.detach_all();
+                let (stream, index, state) = self.main.attach_stream(&mut self.backend, stream);
+                stream.set_connected_channel(common::Channel::Main, index);
+                stream.connect()?;
+                self.active_sink = stream.sink_name();
+                self.device.send(common::HostMessage::UpdateChannelState(
+                    common::Channel::Main,
+                    state,
+                ))?;
+            }
+            backend::Event::NewPeakData(ch, index) => {
+                let channel = match ch {
+                    common::Channel::Main => &mut self.main,
+                    channel::UNASSIGNED => {
+                        self.unassigned.update_peak(index);
+                        return Ok(());
+                    }
+                    ch => match self.channels.get_mut(ch.to_index()) {
+                        Some(channel) => channel,
+                        None => return Ok(()),
+                    },
+                };
+                let mut peak = channel.update_peak(index)?;
+                for multi in self.config.sink_peak_multiplier.iter() {
+                    if self.active_sink.as_deref() == Some(&multi.sink_name) {
+                        peak *= multi.multiplier;
+                        break;
+                    }
+                }
+                self.peak_frames.update(ch, channel.update_meter(peak));
+            }
+            backend::Event::SinkInputAdded(info) => {
+                self.sink_inputs.insert(info.index, info.clone());
+                // check whether this sink-input should be connected to one of our channels - if
+                // yes, request a stream for it.
+                if let Some(index) = self.channel_for_sink_input(&info) {
+                    let ch = common::Channel::from_index(index);
+                    log::debug!(
+                        "Attached stream \"{}/{}\" to channel {:?}",
+                        info.name.as_deref().unwrap_or(""),
+                        info.application.as_deref().unwrap_or(""),
+                        ch
+                    );
+                    self.request_stream(backend::StreamInfo::SinkInput(info), ch)?;
+                } else if self.learns() {
+                    self.request_stream(backend::StreamInfo::SinkInput(info), channel::UNASSIGNED)?;
+                }
+            }
+            backend::Event::NewSinkInput(channel::UNASSIGNED, stream) => {
+                // only monitor it if it was not claimed by a channel in the meantime
+                if self.take_request(channel::UNASSIGNED, &stream)
+                    && !self.unassigned.contains(stream.id())
+                    && self
+                        .channels
+                        .iter()
+                        .all(|channel| channel.index_for_stream(stream.id()).is_none())
+                {
+                    let (stream, index) = self.unassigned.attach_stream(stream);
+                    stream.set_connected_channel(channel::UNASSIGNED, index);
+                    stream.connect()?;
+                }
+            }
+            backend::Event::NewSinkInput(ch, stream) => self.attach_stream(ch, stream, true)?,
+            backend::Event::SinkInputChanged(info) => {
+                self.sink_inputs.insert(info.index, info.clone());
+                for channel in self.channels.iter_mut() {
+                    channel.sync_volume(backend::StreamId::SinkInput(info.index), &info.volume);
+                }
+                let id = backend::StreamId::SinkInput(info.index);
+                let best_match = self.channel_for_sink_input(&info);
+                // check if this channel already owns the sink-input
+                if let Some(cidx) =
+                    best_match.filter(|&cidx| self.channels[cidx].index_for_stream(id).is_none())
+                {
+                    log::debug!(
+                        "Moved stream \"{}/{}\" to channel {:?}",
+                        info.name.as_deref().unwrap_or(""),
+                        info.application.as_deref().unwrap_or(""),
+                        common::Channel::from_index(cidx)
+                    );
+                    self.move_sink_input(info, Some(cidx))?;
+                } else if best_match.is_none() && self.is_claimed(id) {
+                    log::debug!(
+                        "Detached stream \"{}/{}\" which no channel matches anymore",
+                        info.name.as_deref().unwrap_or(""),
+                        info.application.as_deref().unwrap_or("")
+                    );
+                    self.move_sink_input(info, None)?;
+                }
+            }
+            backend::Event::SinkInputRemoved(index) => {
+                self.sink_inputs.remove(&index);
+                self.assignments.remove(&index);
+                self.unassigned
+                    .try_drop_stream(backend::StreamId::SinkInput(index));
+                self.drop_stream(backend::StreamId::SinkInput(index))?;
+            }
+            backend::Event::SinkAdded(info) => {
+                if let Some(name) = &info.name {
+                    self.sinks.insert(info.index, name.clone());
+                }
+                if let Some(index) = self.channels.iter().position(|c| c.match_sink(&info)) {
+                    let ch = common::Channel::from_index(index);
+                    log::debug!(
+                        "Attached sink \"{}\" to channel {:?}",
+                        info.name.as_deref().unwrap_or(""),
+                        ch
+                    );
+                    self.request_stream(backend::StreamInfo::Sink(info), ch)?;
+                }
+            }
+            backend::Event::NewSink(ch, stream) => self.attach_stream(ch, stream, false)?,
+            backend::Event::SinkChanged(info) => {
+                // keep volume and mute LED in sync when the sink is changed from elsewhere
+                let id = backend::StreamId::Sink(info.index);
+                self.main.sync_volume(id, &info.volume);
+                if let Some(new_state) = self.main.sync_mute(id, info.mute) {
+                    self.device.send(common::HostMessage::UpdateChannelState(
+                        common::Channel::Main,
+                        new_state,
+                    ))?;
+                }
+                self.sync_stream(id, &info.volume, Some(info.mute))?;
+            }
+            backend::Event::SinkRemoved(index) => {
+                self.sinks.remove(&index);
+                self.drop_stream(backend::StreamId::Sink(index))?;
+            }
+            backend::Event::SourceOutputAdded(info) => {
+                if let Some(index) =
+                    channel::find_best_match(&self.channels, |c| c.match_source_output(&info))
+                {
+                    let ch = common::Channel::from_index(index);
+                    log::debug!(
+                        "Attached recording stream \"{}/{}\" to channel {:?}",
+                        info.name.as_deref().unwrap_or(""),
+                        info.application.as_deref().unwrap_or(""),
+                        ch
+                    );
+                    self.request_stream(backend::StreamInfo::SourceOutput(info), ch)?;
+                }
+            }
+            backend::Event::NewSourceOutput(ch, stream) => self.attach_stream(ch, stream, true)?,
+            backend::Event::SourceOutputChanged(info) => {
+                let id = backend::StreamId::SourceOutput(info.index);
+                self.sync_stream(id, &info.volume, None)?;
+                let best_match =
+                    channel::find_best_match(&self.channels, |c| c.match_source_output(&info));
+                // check if this channel already owns the source-output
+                if let Some(cidx) =
+                    best_match.filter(|&cidx| self.channels[cidx].index_for_stream(id).is_none())
+                {
+                    let ch = common::Channel::from_index(cidx);
+                    log::debug!(
+                        "Moved recording stream \"{}/{}\" to channel {:?}",
+                        info.name.as_deref().unwrap_or(""),
+                        info.application.as_deref().unwrap_or(""),
+                        ch
+                    );
+                    // remove from previous owner
+                    self.drop_stream(id)?;
+                    self.request_stream(backend::StreamInfo::SourceOutput(info), ch)?;
+                } else if best_match.is_none() && self.is_claimed(id) {
+                    log::debug!(
+                        "Detached recording stream \"{}/{}\" which no channel matches anymore",
+                        info.name.as_deref().unwrap_or(""),
+                        info.application.as_deref().unwrap_or("")
+                    );
+                    self.drop_stream(id)?;
+                }
+            }
+            backend::Event::SourceOutputRemoved(index) => {
+                self.drop_stream(backend::StreamId::SourceOutput(index))?;
+            }
+            backend::Event::SourceAdded(info) => {
+                if let Some(index) = self.channels.iter().position(|c| c.match_source(&info)) {
+                    let ch = common::Channel::from_index(index);
+                    log::debug!(
+                        "Attached source \"{}\" to channel {:?}",
+                        info.name.as_deref().unwrap_or(""),
+                        ch
+                    );
+                    self.request_stream(backend::StreamInfo::Source(info), ch)?;
+                }
+            }
+            backend::Event::NewSource(ch, stream) => self.attach_stream(ch, stream, true)?,
+            backend::Event::SourceChanged(info) => {
+                // keep the mute LED in sync when the source is muted from elsewhere
+                let id = backend::StreamId::Source(info.index);
+                self.sync_stream(id, &info.volume, Some(info.mute))?;
+            }
+            backend::Event::SourceRemoved(index) => {
+                self.drop_stream(backend::StreamId::Source(index))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attach a stream which was requested for a channel, unless the channel has it already.
+    ///
+    /// The configured label of the channel is sent to the display either way.  With `show`, the
+    /// stream's icon is sent as well and its application name stands in for a missing label.
+    fn attach_stream(
+        &mut self,
+        ch: common::Channel,
+        stream: B::Stream,
+        show: bool,
+    ) -> anyhow::Result<()> {
+        if !self.take_request(ch, &stream) {
+            return Ok(());
+        }
+        let channel = match self.channels.get_mut(ch.to_index()) {
+            Some(channel) => channel,
+            // requested for a channel which is gone by now
+            None => return Ok(()),
+        };
+        if channel.index_for_stream(stream.id()).is_some() {
+            return Ok(());
+        }
+        let label = channel.label().map(str::to_owned);
+        let (stream, index, state) = channel.attach_stream(&mut self.backend, stream);
+        stream.set_connected_channel(ch, index);
+        stream.connect()?;
+        self.device
+            .send(common::HostMessage::UpdateChannelState(ch, state))?;
+        if show {
+            show_icon(&mut self.device, ch, stream, &self.icon_mappings)?;
+        }
+        if let Some(label) = label.or_else(|| stream.get_label().filter(|_| show)) {
+            self.device.send_label(ch, &label)?;
+        }
+        Ok(())
+    }
+
+    /// Ask the backend for a stream for the given channel.  It arrives as one of the `New*` events.
+    fn request_stream(
+        &mut self,
+        info: backend::StreamInfo,
+        ch: common::Channel,
+    ) -> anyhow::Result<()> {
+        self.requested.insert(info.id(), ch);
+        match info {
+            backend::StreamInfo::Sink(info) => self.backend.request_sink_stream(info, ch),
+            backend::StreamInfo::SinkInput(info) => {
+                self.backend.request_sink_input_stream(info, ch);
+                Ok(())
+            }
+            backend::StreamInfo::Source(info) => self.backend.request_source_stream(info, ch),
+            backend::StreamInfo::SourceOutput(info) => {
+                self.backend.request_source_output_stream(info, ch)
+            }
+        }
+    }
+
+    /// Check whether a stream arriving for a channel answers the latest request for it.
+    ///
+    /// Streams requested before the channels were rebuilt or before the stream moved elsewhere
+    /// must be dropped, their channel index might mean something else by now.
+    fn take_request(&mut self, ch: common::Channel, stream: &B::Stream) -> bool {
+        if self.requested.get(&stream.id()) == Some(&ch) {
+            self.requested.remove(&stream.id());
+            true
+        } else {
+            log::debug!(
+                "Dropping stale stream for {} on channel {:?}",
+                stream.info().description(),
+                ch
+            );
+            false
+        }
+    }
+
+    /// Take over an external change of a stream's volume and mute state on whichever channel it
+    /// is attached to.
+    fn sync_stream(
+        &mut self,
+        id: backend::StreamId,
+        volume: &[f32],
+        mute: Option<bool>,
+    ) -> anyhow::Result<()> {
+        for (ch, channel) in self.channels.iter_mut().enumerate() {
+            channel.sync_volume(id, volume);
+            if let Some(new_state) = mute.and_then(|mute| channel.sync_mute(id, mute)) {
+                self.device.send(common::HostMessage::UpdateChannelState(
+                    common::Channel::from_index(ch),
+                    new_state,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Detach a stream from whichever channel it is attached to and forget any pending request.
+    fn drop_stream(&mut self, id: backend::StreamId) -> anyhow::Result<()> {
+        self.requested.remove(&id);
+        for (ch, channel) in self.channels.iter_mut().enumerate() {
+            if channel.index_for_stream(id).is_some() {
+                let new_state = channel.try_drop_stream(id);
+                self.device.send(common::HostMessage::UpdateChannelState(
+                    common::Channel::from_index(ch),
+                    new_state,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a stream is attached to one of the channels or on its way to one.
+    fn is_claimed(&self, id: backend::StreamId) -> bool {
+        self.channels
+            .iter()
+            .any(|channel| channel.index_for_stream(id).is_some())
+            || matches!(self.requested.get(&id), Some(&ch) if ch != channel::UNASSIGNED)
+    }
+
+    /// The channel a sink-input belongs to: assigned by hand, pinned, or by the property matches.
+    fn channel_for_sink_input(&self, info: &backend::SinkInputInfo) -> Option<usize> {
+        self.assignments
+            .get(&info.index)
+            .copied()
+            .or_else(|| {
+                self.state
+                    .pinned_channel(self.profile, &info.properties)
+                    .filter(|&index| index < self.channels.len())
+            })
+            .or_else(|| channel::find_best_match(&self.channels, |c| c.match_sink_input(info)))
+    }
+
+    /// Detach a sink-input from all channels and attach it to the channel with the given index.
+    ///
+    /// Without a channel, it is only monitored for learn mode, if any channel learns at all.
+    fn move_sink_input(
+        &mut self,
+        info: backend::SinkInputInfo,
+        index: Option<usize>,
+    ) -> anyhow::Result<()> {
+        self.drop_stream(backend::StreamId::SinkInput(info.index))?;
+        self.unassigned
+            .try_drop_stream(backend::StreamId::SinkInput(info.index));
+        let ch = match index {
+            Some(index) => common::Channel::from_index(index),
+            None if self.learns() => channel::UNASSIGNED,
+            None => return Ok(()),
+        };
+        self.request_stream(backend::StreamInfo::SinkInput(info), ch)
+    }
+
+    /// Whether a gesture of any channel learns streams, which needs the unassigned ones monitored.
+    fn learns(&self) -> bool {
+        self.channels
+            .iter()
+            .any(|channel| channel.buttons().learns())
+    }
+
+    /// Handle all pending messages from the mixer.
+    pub fn handle_device_messages(&mut self) -> anyhow::Result<()> {
+        while let Some(message) = self
+            .device
+            .try_recv()
+            .context("failed reading from mixer")?
+        {
+            match message {
+                common::DeviceMessage::UpdateVolume(ch, volume) => {
+                    log::debug!("Set channel {:?} to {:6.2} %", ch, volume * 100.0);
+                    match ch {
+                        common::Channel::Main => self.main.update_volume(&mut self.backend, volume),
+                        ch => match self.channels.get_mut(ch.to_index()) {
+                            Some(channel) => channel.update_volume(&mut self.backend, volume),
+                            None => log::debug!("Ignoring fader of unconfigured channel {:?}", ch),
+                        },
+                    }
+                }
+                common::DeviceMessage::Button(ch, event) => self.handle_button(ch, event)?,
+                common::DeviceMessage::Hello(_) => {
+                    log::debug!("Ignoring repeated handshake from the mixer.");
+                }
+                common::DeviceMessage::IconStatus(sequence, status) => {
+                    self.device.handle_icon_status(sequence, status)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_button(
+        &mut self,
+        ch: common::Channel,
+        event: common::ButtonEvent,
+    ) -> anyhow::Result<()> {
+        let buttons = channel_buttons(self.config, &self.channels, ch);
+        let action = match buttons.action(event) {
+            Some(action) => action,
+            None => {
+                log::trace!("Button of channel {:?} {:?}.", ch, event);
+                return Ok(());
+            }
+        };
+        log::debug!("{:?} on channel {:?}: {:?}", event, ch, action);
+        match action {
+            config::ButtonAction::None => (),
+            config::ButtonAction::Mute => {
+                let new_state = match ch {
+                    common::Channel::Main => self.main.toggle_mute(&mut self.backend),
+                    ch => match self.channels.get_mut(ch.to_index()) {
+                        Some(channel) => channel.toggle_mute(&mut self.backend),
+                        None => common::ChannelState::Inactive,
+                    },
+                };
+                match new_state {
+                    common::ChannelState::Running => log::debug!("Unmuting channel {:?}.", ch),
+                    common::ChannelState::Muted => log::debug!("Muting channel {:?}.", ch),
+                    common::ChannelState::Inactive => {
+                        log::debug!("Mute event for inactive channel {:?}", ch)
+                    }
+                }
+                self.device
+                    .send(common::HostMessage::UpdateChannelState(ch, new_state))?;
+            }
+            config::ButtonAction::Solo if ch == common::Channel::Main => {
+                log::debug!("Ignoring solo gesture on the main channel.");
+            }
+            config::ButtonAction::Solo if ch.to_index() < self.channels.len() => {
+                let index = ch.to_index();
+                // Soloing the same channel again unmutes everything.
+                self.solo = if self.solo == Some(index) {
+                    log::info!("Unmuting all channels.");
+                    None
+                } else {
+                    log::info!("Soloing channel {}.", index + 1);
+                    Some(index)
+                };
+                for (i, channel) in self.channels.iter_mut().enumerate() {
+                    let mute = matches!(self.solo, Some(solo) if solo != i);
+                    let new_state = channel.set_mute(&mut self.backend, mute);
+                    self.device.send(common::HostMessage::UpdateChannelState(
+                        common::Channel::from_index(i),
+                        new_state,
+                    ))?;
+                }
+            }
+            config::ButtonAction::Solo => {
+                log::debug!("Ignoring solo for channel {:?}.", ch);
+            }
+            config::ButtonAction::PlayPause => match ch {
+                common::Channel::Main => play_pause(&self.main),
+                ch => match self.channels.get(ch.to_index()) {
+                    Some(channel) => play_pause(channel),
+                    None => play_pause(&self.main),
+                },
+            },
+            config::ButtonAction::CycleOutput => {
+                let (sinks, active_sink) = (&self.sinks, self.active_sink.as_ref());
+                let next = sinks
+                    .values()
+                    .skip_while(|name| Some(*name) != active_sink)
+                    .nth(1)
+                    .or_else(|| sinks.values().next());
+                match next {
+                    Some(name) if Some(name) != active_sink => {
+                        log::info!("Switching output to {:?}.", name);
+                        self.backend.set_default_sink(name);
+                    }
+                    _ => log::debug!("There is no other sink to switch to."),
+                }
+            }
+            config::ButtonAction::NextProfile => {
+                self.next_profile = Some(self.config.next_profile(self.profile).to_owned());
+            }
+            config::ButtonAction::Learn if ch == common::Channel::Main => {
+                log::debug!("Ignoring learn gesture on the main channel.");
+            }
+            config::ButtonAction::Learn => self.learn(ch)?,
+        }
+        Ok(())
+    }
+
+    /// Assign the loudest unassigned stream to a channel and add a matching rule for it to the
+    /// configuration.
+    fn learn(&mut self, ch: common::Channel) -> anyhow::Result<()> {
+        let index = ch.to_index();
+        let info = match self.unassigned.loudest().map(|stream| stream.info()) {
+            Some(backend::StreamInfo::SinkInput(info)) if index < self.channels.len() => {
+                info.clone()
+            }
+            Some(_) => {
+                log::info!("Cannot learn a stream for unconfigured channel {:?}.", ch);
+                return Ok(());
+            }
+            None => {
+                log::info!("No unassigned stream is playing, nothing to learn.");
+                return Ok(());
+            }
+        };
+        let identity = match state::identity(&info.properties) {
+            Some(identity) => identity,
+            None => {
+                log::info!(
+                    "Sink-input {} does not report any application properties.",
+                    info.index
+                );
+                return Ok(());
+            }
+        };
+
+        log::info!("Learned {:?} for channel {}.", identity, index + 1);
+        // The configuration watcher re-attaches all streams with the new rule in place.
+        if let Err(e) = config::add_match_set(&self.config_path, self.profile, index, &identity) {
+            log::warn!(
+                "Failed adding the learned stream to the configuration: {:?}",
+                e
+            );
+        }
+        self.move_sink_input(info, Some(index))?;
+        self.device.send(common::HostMessage::Blink(ch))
+    }
+
+    /// Send due meter levels and sync LED changes to the mixer.
+    pub fn update_device(&mut self) -> anyhow::Result<()> {
+        for message in self.peak_frames.poll(std::time::Instant::now()) {
+            self.device.send(message)?;
+        }
+
+        // Light the sync LED of channels whose fader has yet to pick up the actual volume.
+        if let Some(out_of_sync) = self.main.take_sync_change() {
+            self.device.send(common::HostMessage::SetSyncLed(
+                common::Channel::Main,
+                out_of_sync,
+            ))?;
+        }
+        for (ch, channel) in self.channels.iter_mut().enumerate() {
+            if let Some(out_of_sync) = channel.take_sync_change() {
+                self.device.send(common::HostMessage::SetSyncLed(
+                    common::Channel::from_index(ch),
+                    out_of_sync,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute a command from the control socket and build the reply for the client.
+    pub fn handle_command(
+        &mut self,
+        command: &control::Command,
+    ) -> anyhow::Result<Result<String, String>> {
+        let reply = match command {
+            control::Command::Status => {
+                let mut text = format!("profile: {}\n", self.profile);
+                let all_channels = std::iter::once((common::Channel::Main, &self.main)).chain(
+                    self.channels
+                        .iter()
+                        .enumerate()
+                        .map(|(index, channel)| (common::Channel::from_index(index), channel)),
+                );
+                for (ch, channel) in all_channels {
+                    text += &format!("{}: {:?}", channel_label(ch), channel.state());
+                    if let Some(volume) = channel.actual_volume() {
+                        text += &format!(", {:.0} %", volume * 100.0);
+                    }
+                    text += "\n";
+                    for stream in channel.streams() {
+                        text += &format!("  {} ({:?})\n", stream.info().description(), stream.id());
+                    }
+                }
+                Ok(text)
+            }
+            control::Command::Streams => Ok(self
+                .sink_inputs
+                .values()
+                .map(|info| format!("{:#?}\n", info))
+                .collect()),
+            control::Command::Assign { stream, channel } => {
+                match self.sink_inputs.get(stream).cloned() {
+                    Some(info) if *channel < self.channels.len() => {
+                        self.assignments.insert(*stream, *channel);
+                        self.move_sink_input(info, Some(*channel))?;
+                        Ok(format!(
+                            "Assigned sink-input {} to channel {}.\n",
+                            stream,
+                            channel + 1
+                        ))
+                    }
+                    Some(_) => Err(format!("there is no channel {}", channel + 1)),
+                    None => Err(format!("there is no sink-input {}", stream)),
+                }
+            }
+            control::Command::Pin { stream, channel } => {
+                match self
+                    .sink_inputs
+                    .get(stream)
+                    .cloned()
+                    .map(|info| (state::identity(&info.properties), info))
+                {
+                    Some((Some(identity), info)) if *channel < self.channels.len() => {
+                        log::info!("Pinning {:?} to channel {}.", identity, channel + 1);
+                        self.state.pin(self.profile, identity, *channel);
+                        self.assignments.remove(stream);
+                        self.move_sink_input(info, Some(*channel))?;
+                        self.state
+                            .save()
+                            .map(|()| {
+                                format!(
+                                    "Pinned sink-input {} to channel {}.\n",
+                                    stream,
+                                    channel + 1
+                                )
+                            })
+                            .map_err(|e| format!("{:#}", e))
+                    }
+                    Some((Some(_), _)) => Err(format!("there is no channel {}", channel + 1)),
+                    Some((None, _)) => Err(format!(
+                        "sink-input {} does not report any application properties",
+                        stream
+                    )),
+                    None => Err(format!("there is no sink-input {}", stream)),
+                }
+            }
+            control::Command::Unpin { stream } => {
+                match self
+                    .sink_inputs
+                    .get(stream)
+                    .cloned()
+                    .map(|info| (state::identity(&info.properties), info))
+                {
+                    Some((Some(identity), info)) if self.state.unpin(self.profile, &identity) => {
+                        log::info!("Unpinning {:?}.", identity);
+                        self.assignments.remove(stream);
+                        let index = self.channel_for_sink_input(&info);
+                        self.move_sink_input(info, index)?;
+                        self.state
+                            .save()
+                            .map(|()| format!("Unpinned sink-input {}.\n", stream))
+                            .map_err(|e| format!("{:#}", e))
+                    }
+                    Some(_) => Err(format!("sink-input {} is not pinned", stream)),
+                    None => Err(format!("there is no sink-input {}", stream)),
+                }
+            }
+            control::Command::Mute(arg) => {
+                match resolve_channel(*arg, &mut self.main, &mut self.channels) {
+                    Ok((ch, channel)) => {
+                        let new_state = channel.toggle_mute(&mut self.backend);
+                        self.device
+                            .send(common::HostMessage::UpdateChannelState(ch, new_state))?;
+                        Ok(format!("{}: {:?}\n", channel_label(ch), new_state))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            control::Command::Volume(arg, volume) => {
+                let backend = &mut self.backend;
+                resolve_channel(*arg, &mut self.main, &mut self.channels).map(|(ch, channel)| {
+                    channel.set_volume(backend, *volume);
+                    format!("{}: {:.0} %\n", channel_label(ch), volume * 100.0)
+                })
+            }
+            control::Command::Profile(None) => Ok(self
+                .config
+                .profile_names()
+                .map(|name| {
+                    let marker = if name == self.profile.as_str() {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    format!("{} {}\n", marker, name)
+                })
+                .collect()),
+            control::Command::Profile(Some(name)) => {
+                if self.config.profile_channels(name).is_some() {
+                    self.next_profile = Some(name.clone());
+                    Ok(format!("Switching to profile {:?}.\n", name))
+                } else {
+                    Err(format!("there is no profile {:?}", name))
+                }
+            }
+        };
+        Ok(reply)
+    }
+
+    /// Take over a changed configuration file.  The channels are rebuilt by the next
+    /// [`Daemon::apply_changes`].
+    pub fn reload(&mut self, new_config: config::Config) {
+        log::info!("Configuration changed, re-attaching all streams.");
+        // The peak rate is applied along with the channels, everything else needs a reconnect.
+        if new_config.backend != self.config.backend
+            || new_config.connection.sudo_hack != self.config.connection.sudo_hack
+        {
+            log::warn!("Changes to [backend] and `sudo-hack` need a restart to apply.");
+        }
+        *self.config = new_config;
+        if self.config.profile_channels(self.profile).is_none() {
+            log::warn!(
+                "Profile {:?} is gone, switching to the default.",
+                self.profile
+            );
+            *self.profile = String::from(config::DEFAULT_PROFILE);
+        }
+        self.reattach = true;
+    }
+
+    /// Switch profiles and rebuild the channels if this was requested since the last call.
+    pub fn apply_changes(&mut self) -> anyhow::Result<()> {
+        if let Some(name) = self
+            .next_profile
+            .take()
+            .filter(|name| self.config.profile_channels(name).is_some())
+        {
+            log::info!("Switching to profile {:?}.", name);
+            *self.profile = name;
+            self.reattach = true;
+        }
+
+        if !std::mem::replace(&mut self.reattach, false) {
+            return Ok(());
+        }
+        // Assignments by hand and outstanding requests refer to the old channels.
+        self.assignments.clear();
+        self.requested.clear();
+        // All streams are matched again, those no channel claims end up here again.
+        self.unassigned = channel::Unassigned::default();
+        // Dropping the old channels detaches all their streams.
+        let old_count = self.channels.len();
+        self.solo = None;
+        self.channels = channels_from_config(self.config, self.profile);
+        self.icon_mappings = self.config.profile_icon_mappings(self.profile);
+        self.main.set_meter(self.config.meter);
+        let channel_count = self.device.info().channel_count;
+        self.peak_frames =
+            meter::Frames::new(self.config.connection.peak_rate, channel_count.into());
+        check_channel_count(channel_count, self.channels.len());
+        for index in 0..old_count.max(self.channels.len()) {
+            self.device.send(common::HostMessage::UpdateChannelState(
+                common::Channel::from_index(index),
+                common::ChannelState::Inactive,
+            ))?;
+        }
+        // The gestures might have changed as well.
+        for ch in std::iter::once(common::Channel::Main)
+            .chain((0..old_count.max(self.channels.len())).map(common::Channel::from_index))
+        {
+            let double_press = channel_buttons(self.config, &self.channels, ch).uses_double_press();
+            self.device
+                .send(common::HostMessage::SetDoublePress(ch, double_press))?;
+        }
+        // Match all existing streams against the new channels, which also re-sends their icons.
+        // The fader positions are requested again for the new channels' volumes.
+        self.backend.rescan();
+        self.device.send(common::HostMessage::ForceUpdate)
+    }
+}
+
+fn channels_from_config<S: backend::Stream>(
+    config: &config::Config,
+    profile: &str,
+) -> Vec<channel::Channel<S>> {
+    config
+        .profile_channels(profile)
+        .unwrap_or(&config.channels)
+        .iter()
+        .map(|channel| channel::Channel::new(Some(channel), channel.meter.unwrap_or(config.meter)))
+        .collect()
+}
+
+/// The button gestures of a channel, the defaults for channels which are not configured.
+fn channel_buttons<S: backend::Stream>(
+    config: &config::Config,
+    channels: &[channel::Channel<S>],
+    ch: common::Channel,
+) -> config::Buttons {
+    match ch {
+        common::Channel::Main => config.main_buttons,
+        ch => channels
+            .get(ch.to_index())
+            .map(|channel| channel.buttons())
+            .unwrap_or_default(),
+    }
+}
+
+/// Name of a channel as used on the control socket.
+fn channel_label(ch: common::Channel) -> String {
+    match ch {
+        common::Channel::Main => "main".to_owned(),
+        ch => format!("channel {}", ch.to_index() + 1),
+    }
+}
+
+fn resolve_channel<'a, S: backend::Stream>(
+    arg: control::ChannelArg,
+    main: &'a mut channel::Channel<S>,
+    channels: &'a mut [channel::Channel<S>],
+) -> Result<(common::Channel, &'a mut channel::Channel<S>), String> {
+    match arg {
+        control::ChannelArg::Main => Ok((common::Channel::Main, main)),
+        control::ChannelArg::App(index) => channels
+            .get_mut(index)
+            .map(|channel| (common::Channel::from_index(index), channel))
+            .ok_or_else(|| format!("there is no channel {}", index + 1)),
+    }
+}
+
+fn check_channel_count(device: u8, configured: usize) {
+    if usize::from(device) != configured {
+        log::warn!(
+            "The mixer has {} channels but the configuration defines {}.",
+            device,
+            configured
+        );
+    }
+    if usize::from(device) > common::MAX_PEAK_CHANNELS {
+        log::warn!(
+            "Only the first {} of the mixer's {} channels get level meters.",
+            common::MAX_PEAK_CHANNELS,
+            device
+        );
+    }
+}
+
+/// Show the icon of a stream which was just attached to a channel.
+fn show_icon<S: backend::Stream>(
+    device: &mut impl device::Device,
+    ch: common::Channel,
+    stream: &S,
+    icon_mappings: &[config::IconMapping],
+) -> anyhow::Result<()> {
+    if let Some(icon_name) = stream.get_icon_name(icon_mappings) {
+        log::debug!("Icon {:?} for Channel {:?}", icon_name, ch);
+        if let Some(icon_data) = icon::get_icon_data(&icon_name) {
+            device.send_icon(ch, &icon_data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Pause or resume the media player of the first application on a channel, or whichever player
+/// `playerctl` picks if there is none.
+fn play_pause<S: backend::Stream>(channel: &channel::Channel<S>) {
+    // MPRIS players are mostly named like their application, just in lowercase.
+    let player = channel.streams().find_map(|stream| {
+        stream
+            .info()
+            .properties()
+            .and_then(|p| p.get("application.name"))
+            .map(|name| name.to_lowercase())
+    });
+    let mut command = std::process::Command::new("playerctl");
+    if let Some(player) = &player {
+        command.arg(format!("--player={}", player));
+    }
+    // Waiting for playerctl would stall the event loop, so it is reaped from a thread.
+    match command.arg("play-pause").spawn() {
+        Ok(mut child) => {
+            std::thread::spawn(move || match child.wait() {
+                Ok(status) if status.success() => (),
+                Ok(status) => log::warn!("playerctl {:?} failed: {}", player, status),
+                Err(e) => log::warn!("Failed waiting for playerctl: {}", e),
+            });
+        }
+        Err(e) => log::warn!("Failed running playerctl: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, StreamId, StreamInfo};
+    use crate::mock::{MockBackend, MockDevice};
+    use common::{Channel, ChannelState, HostMessage};
+
+    const CONFIG: &str = r#"
+        icon-mappings = []
+
+        [connection]
+        sudo-hack = false
+
+        [[channels]]
+        [[channels.property-matches]]
+        "application.name" = "Firefox"
+
+        [[channels]]
+        [[channels.property-matches]]
+        "application.name" = "mpv"
+    "#;
+
+    /// A daemon for a mixer with two channels, living for the rest of the test run.
+    fn daemon(config: &str) -> Daemon<'static, MockBackend, MockDevice> {
+        let config = Box::leak(Box::new(config::parse(config).unwrap()));
+        let profile = Box::leak(Box::new(String::from(config::DEFAULT_PROFILE)));
+        let state = Box::leak(Box::new(state::State::default()));
+        Daemon::new(
+            config,
+            profile,
+            state,
+            MockDevice::new(2),
+            MockBackend::new(),
+        )
+        .unwrap()
+    }
+
+    /// Let the daemon handle all events, including those for the streams it requested.
+    fn settle(daemon: &mut Daemon<MockBackend, MockDevice>) -> Vec<HostMessage> {
+        daemon.handle_events().unwrap();
+        daemon.backend.iterate(false).unwrap();
+        daemon.handle_events().unwrap();
+        daemon.device.sent.drain(..).collect()
+    }
+
+    fn label(ch: Channel, text: &str) -> HostMessage {
+        HostMessage::SetLabel(ch, common::make_label(text))
+    }
+
+    #[test]
+    fn streams_are_attached_moved_and_removed() {
+        let mut daemon = daemon(CONFIG);
+        settle(&mut daemon);
+
+        let mut info = MockBackend::sink_input(1, &[("application.name", "Firefox")], false);
+        daemon.backend.add(StreamInfo::SinkInput(info.clone()));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(0), ChannelState::Running),
+                label(Channel::App(0), "Firefox"),
+            ]
+        );
+
+        info.application = Some("mpv".to_owned());
+        info.properties
+            .insert("application.name".to_owned(), "mpv".to_owned());
+        daemon.backend.change(StreamInfo::SinkInput(info));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(0), ChannelState::Inactive),
+                HostMessage::UpdateChannelState(Channel::App(1), ChannelState::Running),
+                label(Channel::App(1), "mpv"),
+            ]
+        );
+
+        daemon.backend.remove(StreamId::SinkInput(1));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![HostMessage::UpdateChannelState(
+                Channel::App(1),
+                ChannelState::Inactive
+            )]
+        );
+        assert_eq!(
+            daemon.handle_command(&control::Command::Streams).unwrap(),
+            Ok(String::new())
+        );
+    }
+
+    #[test]
+    fn solo_on_main_is_ignored() {
+        let source = format!("main-buttons = {{ double-press = \"solo\" }}\n{}", CONFIG);
+        let mut daemon = daemon(&source);
+        // only buttons with a double press gesture wait for a second press
+        let sent = settle(&mut daemon);
+        assert!(sent.contains(&HostMessage::SetDoublePress(Channel::Main, true)));
+        assert!(sent.contains(&HostMessage::SetDoublePress(Channel::App(0), false)));
+
+        daemon
+            .device
+            .incoming
+            .push_back(common::DeviceMessage::Button(
+                Channel::Main,
+                common::ButtonEvent::DoublePress,
+            ));
+        daemon.handle_device_messages().unwrap();
+        assert_eq!(settle(&mut daemon), Vec::new());
+        assert_eq!(daemon.solo, None);
+    }
+
+    #[test]
+    fn streams_requested_before_reload_are_dropped() {
+        let mut daemon = daemon(CONFIG);
+        settle(&mut daemon);
+
+        // The stream for the first channel is still on its way when the channels get swapped.
+        let info = MockBackend::sink_input(1, &[("application.name", "Firefox")], false);
+        daemon.backend.add(StreamInfo::SinkInput(info));
+        daemon.handle_events().unwrap();
+        let swapped = CONFIG
+            .replacen("Firefox", "Chromium", 1)
+            .replacen("mpv", "Firefox", 1);
+        daemon.reload(config::parse(&swapped).unwrap());
+        daemon.apply_changes().unwrap();
+        daemon.device.sent.clear();
+
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(1), ChannelState::Running),
+                label(Channel::App(1), "Firefox"),
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_follow_profile_switches() {
+        let source = format!(
+            "{}\n[[profiles.music.channels]]\n\
+             [[profiles.music.channels.property-matches]]\n\
+             \"application.name\" = \"mpv\"",
+            CONFIG
+        );
+        let mut daemon = daemon(&source);
+        settle(&mut daemon);
+
+        let info = MockBackend::sink_input(1, &[("application.name", "mpv")], false);
+        daemon.backend.add(StreamInfo::SinkInput(info));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(1), ChannelState::Running),
+                label(Channel::App(1), "mpv"),
+            ]
+        );
+
+        assert!(daemon
+            .handle_command(&control::Command::Profile(Some("music".to_owned())))
+            .unwrap()
+            .is_ok());
+        daemon.apply_changes().unwrap();
+        assert_eq!(daemon.profile.as_str(), "music");
+        daemon.device.sent.clear();
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(0), ChannelState::Running),
+                label(Channel::App(0), "mpv"),
+            ]
+        );
+    }
+
+    #[test]
+    fn pinned_streams_win_over_property_matches() {
+        let mut daemon = daemon(CONFIG);
+        let properties =
+            MockBackend::sink_input(1, &[("application.name", "Firefox")], false).properties;
+        daemon.state.pin(
+            config::DEFAULT_PROFILE,
+            state::identity(&properties).unwrap(),
+            1,
+        );
+        settle(&mut daemon);
+
+        let info = MockBackend::sink_input(1, &[("application.name", "Firefox")], false);
+        daemon.backend.add(StreamInfo::SinkInput(info));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(1), ChannelState::Running),
+                label(Channel::App(1), "Firefox"),
+            ]
+        );
+    }
+
+    #[test]
+    fn loudest_unassigned_stream_is_learned() {
+        let mut daemon = daemon(CONFIG);
+        let path =
+            std::env::temp_dir().join(format!("pavu-mixer-learn-{}.toml", std::process::id()));
+        std::fs::write(&path, CONFIG).unwrap();
+        daemon.config_path = path.clone();
+        settle(&mut daemon);
+
+        for (index, name) in [(1, "vlc"), (2, "Rhythmbox")] {
+            let info = MockBackend::sink_input(index, &[("application.name", name)], false);
+            daemon.backend.add(StreamInfo::SinkInput(info));
+        }
+        assert_eq!(settle(&mut daemon), Vec::new());
+        daemon.backend.push_peak(StreamId::SinkInput(1), 0.2);
+        daemon.backend.push_peak(StreamId::SinkInput(2), 0.5);
+        daemon.handle_events().unwrap();
+
+        daemon
+            .device
+            .incoming
+            .push_back(common::DeviceMessage::Button(
+                Channel::App(0),
+                common::ButtonEvent::LongPress,
+            ));
+        daemon.handle_device_messages().unwrap();
+        let learned = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::Blink(Channel::App(0)),
+                HostMessage::UpdateChannelState(Channel::App(0), ChannelState::Running),
+                label(Channel::App(0), "Rhythmbox"),
+            ]
+        );
+        // the rule added to the configuration claims the stream for the channel from now on
+        let learned = config::parse(&learned.unwrap()).unwrap();
+        let index = channel::find_best_match(
+            &channels_from_config::<crate::mock::MockStream>(&learned, config::DEFAULT_PROFILE),
+            |c| c.match_sink_input(&daemon.sink_inputs[&2]),
+        );
+        assert_eq!(index, Some(0));
+    }
+
+    #[test]
+    fn sinks_show_the_channel_label() {
+        let source = format!(
+            "{}\n[[channels]]\nsink = \"speakers\"\nlabel = \"Speakers\"",
+            CONFIG
+        );
+        let mut daemon = daemon(&source);
+        settle(&mut daemon);
+
+        daemon.backend.add(StreamInfo::Sink(backend::SinkInfo {
+            index: 1,
+            name: Some("speakers".to_owned()),
+            monitoring_source: 2,
+            volume: vec![1.0, 1.0],
+            mute: false,
+        }));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(2), ChannelState::Running),
+                label(Channel::App(2), "Speakers"),
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_no_channel_matches_anymore_are_detached() {
+        let source = CONFIG.replacen(
+            "\"Firefox\"",
+            "\"Firefox\"\nexclude = { \"media.role\" = \"phone\" }",
+            1,
+        );
+        let mut daemon = daemon(&source);
+        settle(&mut daemon);
+
+        let mut info = MockBackend::sink_input(1, &[("application.name", "Firefox")], false);
+        daemon.backend.add(StreamInfo::SinkInput(info.clone()));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![
+                HostMessage::UpdateChannelState(Channel::App(0), ChannelState::Running),
+                label(Channel::App(0), "Firefox"),
+            ]
+        );
+
+        info.properties
+            .insert("media.role".to_owned(), "phone".to_owned());
+        daemon.backend.change(StreamInfo::SinkInput(info));
+        assert_eq!(
+            settle(&mut daemon),
+            vec![HostMessage::UpdateChannelState(
+                Channel::App(0),
+                ChannelState::Inactive
+            )]
+        );
+        assert!(daemon.unassigned.contains(StreamId::SinkInput(1)));
+    }
+
+    #[test]
+    fn unassigned_streams_are_only_monitored_for_learning() {
+        // the long press learns by default
+        let without_learning = CONFIG.replace(
+            "[[channels]]\n",
+            "[[channels]]\nbuttons = { long-press = \"none\" }\n",
+        );
+        for (source, learns) in [(CONFIG, true), (without_learning.as_str(), false)] {
+            let mut daemon = daemon(source);
+            settle(&mut daemon);
+            let info = MockBackend::sink_input(1, &[("application.name", "vlc")], false);
+            daemon.backend.add(StreamInfo::SinkInput(info));
+            assert_eq!(settle(&mut daemon), Vec::new());
+            assert_eq!(daemon.unassigned.contains(StreamId::SinkInput(1)), learns);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Lookup of application icons through the installed `.desktop` files.
+use crate::backend;
+use std::cell::RefCell;
+use std::path;
+use std::time;
+
+/// Properties holding the desktop file id of an application (e.g. its Flatpak id), in the order
+/// they are tried.
+const APP_ID_PROPERTIES: &[&str] = &["pipewire.access.portal.app_id", "application.id"];
+
+/// The relevant keys of the `[Desktop Entry]` group of a desktop file.
+#[derive(Debug, Default, PartialEq)]
+struct Entry {
+    icon: Option<String>,
+    exec: Option<String>,
+    startup_wm_class: Option<String>,
+}
+
+impl Entry {
+    fn parse(source: &str) -> Self {
+        let mut entry = Self::default();
+        let mut in_main_group = false;
+        for line in source.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_main_group = line == "[Desktop Entry]";
+                continue;
+            }
+            if !in_main_group || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim().to_owned()),
+                None => continue,
+            };
+            // localized keys like `Icon[de]` are ignored
+            match key {
+                "Icon" => entry.icon = Some(value),
+                "Exec" => entry.exec = Some(value),
+                "StartupWMClass" => entry.startup_wm_class = Some(value),
+                _ => (),
+            }
+        }
+        entry
+    }
+
+    /// Whether this entry launches the given binary.
+    fn runs(&self, binary: &str) -> bool {
+        let program = self.exec.as_deref().and_then(|exec| {
+            // skip `env VAR=value ...` wrappers
+            exec.split_whitespace()
+                .map(|word| word.trim_matches('"'))
+                .find(|&word| word != "env" && !word.contains('='))
+        });
+        let program_name = program.and_then(|p| path::Path::new(p).file_name()?.to_str());
+        program_name == Some(binary)
+            || self
+                .startup_wm_class
+                .as_deref()
+                .map(|class| class.eq_ignore_ascii_case(binary))
+                .unwrap_or(false)
+    }
+}
+
+/// Directories holding desktop files, most important first.
+fn application_dirs() -> Vec<path::PathBuf> {
+    let home = std::env::var_os("HOME").map(path::PathBuf::from);
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(path::PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_owned());
+
+    data_home
+        .iter()
+        .cloned()
+        .chain(data_dirs.split(':').map(path::PathBuf::from))
+        // Flatpak exports, in case they are missing from XDG_DATA_DIRS
+        .chain(
+            data_home
+                .iter()
+                .map(|dir| dir.join("flatpak/exports/share")),
+        )
+        .chain(std::iter::once(path::PathBuf::from(
+            "/var/lib/flatpak/exports/share",
+        )))
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+fn read_entry(path: &path::Path) -> Option<Entry> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|source| Entry::parse(&source))
+}
+
+fn modified(dir: &path::Path) -> Option<time::SystemTime> {
+    std::fs::metadata(dir)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// All desktop files with an icon, so streams can be looked up without touching the disk.
+struct Index {
+    /// The application directories and their modification time when the index was built.
+    dirs: Vec<(path::PathBuf, Option<time::SystemTime>)>,
+    /// Entries by desktop file id (the file name without `.desktop`), most important first.
+    entries: Vec<(String, Entry)>,
+}
+
+impl Index {
+    fn build() -> Self {
+        let dirs: Vec<_> = application_dirs()
+            .into_iter()
+            .map(|dir| {
+                let modified = modified(&dir);
+                (dir, modified)
+            })
+            .collect();
+        let entries = dirs
+            .iter()
+            .filter_map(|(dir, _)| std::fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|file| file.ok().map(|file| file.path()))
+            .filter(|path| {
+                path.extension()
+                    .map(|ext| ext == "desktop")
+                    .unwrap_or(false)
+            })
+            .filter_map(|path| {
+                let id = path.file_stem()?.to_str()?.to_owned();
+                Some((id, read_entry(&path)?))
+            })
+            .filter(|(_, entry)| entry.icon.is_some())
+            .collect();
+        Self { dirs, entries }
+    }
+
+    /// Whether applications were installed or removed since the index was built.
+    fn is_outdated(&self) -> bool {
+        self.dirs
+            .iter()
+            .any(|(dir, modified_then)| modified(dir) != *modified_then)
+    }
+
+    fn icon_for(&self, properties: &backend::Properties) -> Option<String> {
+        let by_id = APP_ID_PROPERTIES
+            .iter()
+            .filter_map(|name| properties.get(*name))
+            .filter_map(|app_id| self.entries.iter().find(|(id, _)| id == app_id));
+
+        let binary = properties.get("application.process.binary");
+        let by_binary = binary.into_iter().flat_map(|binary| {
+            self.entries
+                .iter()
+                .filter(move |(id, entry)| id == binary || entry.runs(binary))
+        });
+
+        by_id
+            .chain(by_binary)
+            .find_map(|(_, entry)| entry.icon.clone())
+    }
+}
+
+thread_local! {
+    /// Built on the first lookup and rebuilt once an application directory changed.
+    static INDEX: RefCell<Option<Index>> = const { RefCell::new(None) };
+}
+
+/// The icon of the application a stream belongs to, according to its desktop file.
+///
+/// The desktop file is looked up by the application's id first and by its binary otherwise.
+pub fn icon_for(properties: &backend::Properties) -> Option<String> {
+    INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if index.as_ref().map(Index::is_outdated).unwrap_or(true) {
+            *index = Some(Index::build());
+        }
+        index.as_ref()?.icon_for(properties)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_match_desktop_entries() {
+        let entry = Entry::parse(
+            "[Desktop Entry]\n\
+             Name=Spotify\n\
+             Icon[de]=spotify-de\n\
+             Icon=spotify-client\n\
+             Exec=env LD_PRELOAD=x \"/usr/share/spotify/spotify\" %U\n\
+             \n\
+             [Desktop Action Quit]\n\
+             Icon=application-exit\n",
+        );
+        assert_eq!(entry.icon.as_deref(), Some("spotify-client"));
+        assert!(entry.runs("spotify"));
+        assert!(!entry.runs("env"));
+
+        let entry = Entry::parse(
+            "[Desktop Entry]\nExec=/usr/bin/flatpak run org.mozilla.firefox\nStartupWMClass=Firefox",
+        );
+        assert!(entry.runs("firefox"));
+        assert!(!entry.runs("chromium"));
+    }
+
+    #[test]
+    fn look_up_icons_by_id_and_binary() {
+        let entry = |source: &str| Entry::parse(&format!("[Desktop Entry]\n{}", source));
+        let index = Index {
+            dirs: Vec::new(),
+            entries: vec![
+                ("mpv".to_owned(), entry("Icon=mpv\nExec=mpv %U")),
+                (
+                    "org.mozilla.firefox".to_owned(),
+                    entry("Icon=firefox-flatpak\nExec=flatpak run org.mozilla.firefox"),
+                ),
+                ("firefox".to_owned(), entry("Icon=firefox\nExec=firefox %u")),
+            ],
+        };
+        let properties = |pairs: &[(&str, &str)]| -> backend::Properties {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let flatpak = properties(&[
+            ("application.id", "org.mozilla.firefox"),
+            ("application.process.binary", "firefox"),
+        ]);
+        assert_eq!(index.icon_for(&flatpak).as_deref(), Some("firefox-flatpak"));
+        let native = properties(&[("application.process.binary", "firefox")]);
+        assert_eq!(index.icon_for(&native).as_deref(), Some("firefox"));
+        let unknown = properties(&[("application.process.binary", "vlc")]);
+        assert_eq!(index.icon_for(&unknown), None);
+    }
+}
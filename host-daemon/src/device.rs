@@ -0,0 +1,28 @@
+//! Abstraction over the mixer hardware the daemon talks to.
+//!
+//! The daemon only reaches the mixer through [`Device`], so the event handling can run against a
+//! mock device in tests.
+
+/// A connected mixer.
+pub trait Device {
+    /// What the mixer reported about itself when connecting.
+    fn info(&self) -> &common::DeviceInfo;
+
+    fn send(&mut self, msg: common::HostMessage) -> anyhow::Result<()>;
+
+    /// The next message from the mixer, if one is waiting.
+    fn try_recv(&mut self) -> anyhow::Result<Option<common::DeviceMessage>>;
+
+    /// Show an icon on a channel, unless the mixer cannot display icons.
+    fn send_icon(&mut self, ch: common::Channel, icon_data: &[u8]) -> anyhow::Result<()>;
+
+    /// Show a label below a channel's icon, unless the mixer has no display.
+    fn send_label(&mut self, ch: common::Channel, label: &str) -> anyhow::Result<()>;
+
+    /// Handle the mixer's report about an icon transfer.
+    fn handle_icon_status(
+        &mut self,
+        sequence: u16,
+        status: common::IconStatus,
+    ) -> anyhow::Result<()>;
+}
@@ -1,14 +1,25 @@
 use gtk::prelude::IconThemeExt;
 
+/// Load an icon by its name in the icon theme or, as `.desktop` files may specify, by its path.
 pub fn get_icon_data(name: &str) -> Option<Vec<u8>> {
-    let icon_theme = gtk::IconTheme::default()?;
-    let icon = icon_theme
-        .load_icon(
+    let icon = if name.starts_with('/') {
+        gdk_pixbuf::Pixbuf::from_file_at_scale(
             name,
             common::ICON_SIZE as i32,
-            gtk::IconLookupFlags::FORCE_SIZE,
+            common::ICON_SIZE as i32,
+            false,
         )
-        .ok()??;
+        .ok()?
+    } else {
+        let icon_theme = gtk::IconTheme::default()?;
+        icon_theme
+            .load_icon(
+                name,
+                common::ICON_SIZE as i32,
+                gtk::IconLookupFlags::FORCE_SIZE,
+            )
+            .ok()??
+    };
 
     if icon.bits_per_sample() != 8 {
         log::warn!("Icon pixbuf does not use 8-bits-per-sample.");
@@ -20,31 +31,102 @@ pub fn get_icon_data(name: &str) -> Option<Vec<u8>> {
     }
 
     let icon_buffer = icon.read_pixel_bytes();
+    // RGB, plus alpha for most icons - but e.g. JPEG files have none
+    let n_channels = icon.n_channels() as usize;
+    let rowstride = icon.rowstride() as usize;
+
+    let mut pixels = vec![];
+
+    for y in 0..icon.height() as usize {
+        for x in 0..icon.width() as usize {
+            let offset = y * rowstride + x * n_channels;
+            let pixel = &icon_buffer[offset..offset + n_channels];
+            let (r, g, b) = (pixel[0] as u16, pixel[1] as u16, pixel[2] as u16);
+            let alpha = if icon.has_alpha() {
+                pixel[3] as u16
+            } else {
+                255
+            };
+            let (r, g, b) = (r * alpha / 255, g * alpha / 255, b * alpha / 255);
+            let rgb565: u16 = ((r & 0b11111000) << 8) | ((g & 0b11111100) << 3) | (b >> 3);
+            pixels.push(rgb565);
 
-    let mut target_buffer = vec![];
-
-    match icon.n_channels() {
-        4 => {
-            for i in 0..(icon_buffer.len() / 4) {
-                let (r, g, b) = (
-                    icon_buffer[i * 4] as u16,
-                    icon_buffer[i * 4 + 1] as u16,
-                    icon_buffer[i * 4 + 2] as u16,
-                );
-                let alpha = icon_buffer[i * 4 + 3] as u16;
-                let (r, g, b) = (r * alpha / 255, g * alpha / 255, b * alpha / 255);
-                let rgb565: u16 = ((r & 0b11111000) << 8) | ((g & 0b11111100) << 3) | (b >> 3);
-                target_buffer.extend_from_slice(&rgb565.to_be_bytes());
-
-                // print!("\x1B[48;2;{};{};{}m  ", r, g, b);
-                // if i % common::ICON_SIZE == (common::ICON_SIZE - 1) {
-                //     println!("\x1B[0m");
-                // }
-            }
+            // print!("\x1B[48;2;{};{};{}m  ", r, g, b);
+            // if x == (common::ICON_SIZE - 1) {
+            //     println!("\x1B[0m");
+            // }
         }
-        i => todo!("image has {} channels and this is not yet supported", i),
     }
 
-    assert!(target_buffer.len() == common::ICON_SIZE * common::ICON_SIZE * 2);
-    Some(target_buffer)
+    assert!(pixels.len() == common::ICON_SIZE * common::ICON_SIZE);
+    let encoded = encode(&pixels);
+    log::trace!(
+        "Encoded icon {:?} in {} of {} bytes.",
+        name,
+        encoded.len(),
+        common::ICON_BYTES
+    );
+    Some(encoded)
+}
+
+/// Run-length encode RGB565 pixels in the format of [`common::IconDecoder`].
+fn encode(pixels: &[u16]) -> Vec<u8> {
+    let mut encoded = vec![];
+    let mut rest = pixels;
+    while let Some(&pixel) = rest.first() {
+        let run = rest
+            .iter()
+            .take(common::ICON_RUN_MAX)
+            .take_while(|&&p| p == pixel)
+            .count();
+        if run > 1 {
+            encoded.push((run - 1) as u8);
+            encoded.extend_from_slice(&pixel.to_be_bytes());
+            rest = &rest[run..];
+            continue;
+        }
+
+        // collect pixels verbatim until the next run starts
+        let literal = rest
+            .windows(2)
+            .take_while(|pair| pair[0] != pair[1])
+            .count();
+        // the last pixel has no successor, it joins the literal
+        let literal = if literal == rest.len() - 1 {
+            rest.len()
+        } else {
+            literal
+        }
+        .clamp(1, common::ICON_RUN_MAX);
+        encoded.push((literal + 0x7f) as u8);
+        for pixel in &rest[..literal] {
+            encoded.extend_from_slice(&pixel.to_be_bytes());
+        }
+        rest = &rest[literal..];
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_icons_decode_to_the_same_pixels() {
+        let mut pixels = vec![0x0000; 300];
+        pixels.extend((0..300).map(|i| i as u16));
+        pixels.extend([0xf800, 0xf800, 0x07e0, 0x001f]);
+
+        let encoded = encode(&pixels);
+        assert!(encoded.len() < pixels.len() * 2);
+
+        let mut decoded = vec![0x00; pixels.len() * 2];
+        let mut decoder = common::IconDecoder::new();
+        for packet in encoded.chunks(48) {
+            decoder.push(packet, &mut decoded).unwrap();
+        }
+        assert!(decoder.is_complete(decoded.len()));
+        let expected: Vec<u8> = pixels.iter().flat_map(|p| p.to_be_bytes()).collect();
+        assert_eq!(decoded, expected);
+    }
 }
@@ -1,10 +1,23 @@
 use anyhow::Context;
 
+mod backend;
 mod channel;
 mod config;
 mod connection;
+mod control;
+mod daemon;
+mod desktop;
+mod device;
 mod icon;
+mod meter;
+#[cfg(test)]
+mod mock;
 mod pa;
+#[cfg(feature = "pipewire")]
+mod pw;
+mod socket;
+mod state;
+mod wizard;
 
 fn main() -> anyhow::Result<()> {
     env_logger::builder()
@@ -18,14 +31,66 @@ fn main() -> anyhow::Result<()> {
         )
         .init();
 
-    let config: config::Config =
-        confy::load("pavu-mixer", Some("pavu-mixer")).context("failed loading configuration")?;
+    if std::env::args().any(|arg| arg == "--generate-config") {
+        let pa = pa::PulseInterface::init().context("failed initializing pulseaudio client")?;
+        return wizard::run(pa);
+    }
+
+    if std::env::args().any(|arg| arg == "--check-config") {
+        config::check()?;
+        println!("Configuration is valid.");
+        return Ok(());
+    }
+    let mut config = config::load()?;
+    let mut config_watcher = config::Watcher::new()?;
+    let mut profile = String::from(config::DEFAULT_PROFILE);
+    let mut control = control::Server::bind()?;
+    let mut state = state::State::load().unwrap_or_else(|e| {
+        log::warn!("Ignoring invalid state file: {:?}", e);
+        state::State::default()
+    });
 
+    gtk::init()?;
     let mut pavu_mixer =
         connection::PavuMixer::connect(&config.connection).context("failed connecting to mixer")?;
 
     loop {
-        let error = match run(&config, pavu_mixer) {
+        let result = match config.backend.kind {
+            config::BackendKind::Pulseaudio => {
+                let pa =
+                    pa::PulseInterface::init().context("failed initializing pulseaudio client")?;
+                run(
+                    &mut config,
+                    &mut config_watcher,
+                    &mut profile,
+                    &mut control,
+                    &mut state,
+                    pavu_mixer,
+                    pa,
+                )
+            }
+            #[cfg(feature = "pipewire")]
+            config::BackendKind::Pipewire => {
+                let pw =
+                    pw::PipewireInterface::init().context("failed initializing pipewire client")?;
+                run(
+                    &mut config,
+                    &mut config_watcher,
+                    &mut profile,
+                    &mut control,
+                    &mut state,
+                    pavu_mixer,
+                    pw,
+                )
+            }
+            #[cfg(not(feature = "pipewire"))]
+            config::BackendKind::Pipewire => {
+                anyhow::bail!(
+                    "the pipewire backend is not available, rebuild with `--features pipewire`"
+                )
+            }
+        };
+        let error = match result {
             Ok(()) => return Ok(()),
             Err(e) => e,
         };
@@ -44,182 +109,36 @@ fn main() -> anyhow::Result<()> {
     }
 }
 
-fn run(config: &config::Config, mut pavu_mixer: connection::PavuMixer) -> anyhow::Result<()> {
-    gtk::init()?;
-
-    let mut main = channel::Channel::new(None);
-    let mut channels = [
-        channel::Channel::new(Some(config.channel_1.property_matches.clone())),
-        channel::Channel::new(Some(config.channel_2.property_matches.clone())),
-        channel::Channel::new(Some(config.channel_3.property_matches.clone())),
-        channel::Channel::new(Some(config.channel_4.property_matches.clone())),
-    ];
-
-    let mut pa = pa::PulseInterface::init().context("failed initializing pulseaudio client")?;
-
-    let events = pa.take_event_receiver().expect("events channel missing");
-
-    // When we start up, there might still be some messages waiting for us - drop them because we
-    // will request up-to-date ones in the next step.
-    while let Some(message) = pavu_mixer.try_recv().context("failed reading from mixer")? {
-        log::debug!("Dropping stale message from device: {:?}", message);
-    }
-
-    // Put all channels into known state.
-    for ch in [
-        common::Channel::Main,
-        common::Channel::Ch1,
-        common::Channel::Ch2,
-        common::Channel::Ch3,
-        common::Channel::Ch4,
-    ] {
-        pavu_mixer
-            .send(common::HostMessage::UpdateChannelState(
-                ch,
-                common::ChannelState::Inactive,
-            ))
-            .context("failed sending message to device")?;
-        pavu_mixer
-            .send(common::HostMessage::UpdatePeak(ch, 0.0))
-            .context("failed sending message to device")?;
-    }
-
-    // Force an update during daemon startup so we'll have up-to-date values for all channels.
-    pavu_mixer.send(common::HostMessage::ForceUpdate)?;
-
-    let mut active_sink = None;
+fn run<B: backend::Backend>(
+    config: &mut config::Config,
+    config_watcher: &mut config::Watcher,
+    profile: &mut String,
+    control: &mut control::Server,
+    state: &mut state::State,
+    pavu_mixer: connection::PavuMixer,
+    backend: B,
+) -> anyhow::Result<()> {
+    let mut daemon = daemon::Daemon::new(config, profile, state, pavu_mixer, backend)?;
 
     loop {
-        // Handle all pending events from PulseAudio.
-        for event in events.try_iter() {
-            match event {
-                pa::Event::NewDefaultSink(stream) => {
-                    main.detach_all();
-                    let (stream, index, state) = main.attach_stream(&mut pa, stream);
-                    stream.set_connected_channel(common::Channel::Main, index);
-                    stream.connect()?;
-                    active_sink = stream.sink_name();
-                    pavu_mixer.send(common::HostMessage::UpdateChannelState(
-                        common::Channel::Main,
-                        state,
-                    ))?;
-                }
-                pa::Event::NewPeakData(ch, index) => {
-                    let mut peak = match ch {
-                        common::Channel::Main => main.update_peak(index)?,
-                        ch => channels[ch.to_index()].update_peak(index)?,
-                    };
-                    for multi in config.sink_peak_multiplier.iter() {
-                        if active_sink.as_deref() == Some(&multi.sink_name) {
-                            peak *= multi.multiplier;
-                            break;
-                        }
-                    }
-                    pavu_mixer.send(common::HostMessage::UpdatePeak(ch, peak))?;
-                }
-                pa::Event::SinkInputAdded(info) => {
-                    // check whether this sink-input should be connected to one of our channels -
-                    // if yes, request a stream for it.
-                    for (index, channel) in channels.iter().enumerate() {
-                        if channel.match_sink_input(&info) {
-                            let ch = common::Channel::from_index(index);
-                            log::debug!(
-                                "Attached stream \"{}/{}\" to channel {:?}",
-                                info.name.as_deref().unwrap_or(""),
-                                info.application.as_deref().unwrap_or(""),
-                                ch
-                            );
-                            pa.request_sink_input_stream(info, ch);
-                            break;
-                        }
-                    }
-                }
-                pa::Event::NewSinkInput(ch, stream) => {
-                    let channel = &mut channels[ch.to_index()];
-                    // only add this channel if there isn't one already
-                    if channel
-                        .index_for_sink_input(stream.sink_input_index().unwrap())
-                        .is_none()
-                    {
-                        let (stream, index, state) = channel.attach_stream(&mut pa, stream);
-                        stream.set_connected_channel(ch, index);
-                        stream.connect()?;
-                        pavu_mixer.send(common::HostMessage::UpdateChannelState(ch, state))?;
-                        if let Some(icon_name) = stream.get_icon_name(&config.icon_mappings) {
-                            log::debug!("Icon {:?} for Channel {:?}", icon_name, ch);
-                            if let Some(icon_data) = icon::get_icon_data(&icon_name) {
-                                pavu_mixer.send(common::HostMessage::SetIcon(ch))?;
-                                pavu_mixer.send_bulk(&icon_data)?;
-                            }
-                        }
-                    }
-                }
-                pa::Event::SinkInputChanged(info) => {
-                    for (cidx, channel) in channels.iter().enumerate() {
-                        if channel.match_sink_input(&info) {
-                            // check if this channel already owns the sink-input
-                            if channel.index_for_sink_input(info.index).is_some() {
-                                break;
-                            }
-                            let ch = common::Channel::from_index(cidx);
-                            log::debug!(
-                                "Moved stream \"{}/{}\" to channel {:?}",
-                                info.name.as_deref().unwrap_or(""),
-                                info.application.as_deref().unwrap_or(""),
-                                ch
-                            );
-                            // remove from previous owner
-                            for (ch, channel) in channels.iter_mut().enumerate() {
-                                let new_state = channel.try_drop_stream(info.index);
-                                pavu_mixer.send(common::HostMessage::UpdateChannelState(
-                                    common::Channel::from_index(ch),
-                                    new_state,
-                                ))?;
-                            }
-                            pa.request_sink_input_stream(info, ch);
-                            break;
-                        }
-                    }
-                }
-                pa::Event::SinkInputRemoved(index) => {
-                    for (ch, channel) in channels.iter_mut().enumerate() {
-                        let new_state = channel.try_drop_stream(index);
-                        pavu_mixer.send(common::HostMessage::UpdateChannelState(
-                            common::Channel::from_index(ch),
-                            new_state,
-                        ))?;
-                    }
-                }
-            }
+        daemon.handle_events()?;
+        daemon.handle_device_messages()?;
+        daemon.update_device()?;
+
+        // Answer requests on the control socket.
+        while let Some(request) = control.poll() {
+            let reply = daemon.handle_command(&request.command)?;
+            request.reply(reply);
         }
 
-        // Handle pending messages from the mixer device.
-        while let Some(message) = pavu_mixer.try_recv().context("failed reading from mixer")? {
-            match message {
-                common::DeviceMessage::UpdateVolume(ch, volume) => {
-                    log::debug!("Set channel {:?} to {:6.2} %", ch, volume * 100.0);
-                    match ch {
-                        common::Channel::Main => main.update_volume(&mut pa, volume),
-                        ch => channels[ch.to_index()].update_volume(&mut pa, volume),
-                    }
-                }
-                common::DeviceMessage::ToggleChannelMute(ch) => {
-                    let new_state = match ch {
-                        common::Channel::Main => main.toggle_mute(&mut pa),
-                        ch => channels[ch.to_index()].toggle_mute(&mut pa),
-                    };
-                    match new_state {
-                        common::ChannelState::Running => log::debug!("Unmuting channel {:?}.", ch),
-                        common::ChannelState::Muted => log::debug!("Muting channel {:?}.", ch),
-                        common::ChannelState::Inactive => {
-                            log::debug!("Mute event for inactive channel {:?}", ch)
-                        }
-                    }
-                    pavu_mixer.send(common::HostMessage::UpdateChannelState(ch, new_state))?;
-                }
-            }
+        // Pick up changes to the configuration file.
+        match config_watcher.poll() {
+            Some(Ok(new_config)) => daemon.reload(new_config),
+            Some(Err(e)) => log::warn!("Ignoring invalid configuration: {:?}", e),
+            None => (),
         }
+        daemon.apply_changes()?;
 
-        pa.iterate(true)?;
+        daemon.iterate(true)?;
     }
 }
@@ -0,0 +1,213 @@
+//! Level meter ballistics, turning raw peaks into what the mixer displays.
+use crate::config;
+use std::time;
+
+/// What a meter shows after an update.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    /// Position of the meter, from `0.0` to `1.0`.
+    pub level: f32,
+    /// New position of the peak-hold marker, if it moved.
+    pub hold: Option<f32>,
+}
+
+#[derive(Debug)]
+pub struct Meter {
+    config: config::Meter,
+    level: f32,
+    last_update: Option<time::Instant>,
+    /// Position of the peak-hold marker and when it was set.
+    hold: Option<(f32, time::Instant)>,
+}
+
+impl Meter {
+    pub fn new(config: config::Meter) -> Self {
+        Self {
+            config,
+            level: 0.0,
+            last_update: None,
+            hold: None,
+        }
+    }
+
+    /// Feed a new linear peak amplitude into the meter.
+    pub fn update(&mut self, peak: f32, now: time::Instant) -> Reading {
+        let target = self.config.position_for_peak(peak);
+        let time_constant = if target > self.level {
+            self.config.attack
+        } else {
+            self.config.release
+        };
+        let elapsed = self
+            .last_update
+            .map(|last| now.saturating_duration_since(last).as_secs_f32() * 1000.0);
+        self.level = match elapsed {
+            Some(elapsed) if time_constant > 0.0 => {
+                self.level + (target - self.level) * (1.0 - (-elapsed / time_constant).exp())
+            }
+            _ => target,
+        };
+        self.last_update = Some(now);
+
+        if self.config.peak_hold <= 0.0 {
+            return Reading {
+                level: self.level,
+                hold: None,
+            };
+        }
+        let hold_expired = |(hold, since): (f32, time::Instant)| {
+            self.level >= hold
+                || now.saturating_duration_since(since).as_secs_f32() * 1000.0
+                    >= self.config.peak_hold
+        };
+        let previous = self.hold.map(|(hold, _)| hold);
+        if self.hold.map(hold_expired).unwrap_or(true) {
+            self.hold = Some((self.level, now));
+        }
+        let hold = self.hold.map(|(hold, _)| hold);
+        Reading {
+            level: self.level,
+            hold: hold.filter(|_| hold != previous),
+        }
+    }
+}
+
+/// Collects the meter readings of all channels to send them to the mixer in one message.
+#[derive(Debug)]
+pub struct Frames {
+    interval: time::Duration,
+    last_frame: Option<time::Instant>,
+    main: u8,
+    channels: common::Peaks,
+    /// Peak-hold markers which moved since the last frame.
+    holds: Vec<(common::Channel, f32)>,
+    /// The levels last sent, to skip frames without changes.
+    sent: Option<(u8, common::Peaks)>,
+}
+
+impl Frames {
+    /// Send frames `rate` times per second for the first `channel_count` application channels.
+    pub fn new(rate: f32, channel_count: usize) -> Self {
+        let mut channels = common::Peaks::new();
+        channels
+            .resize_default(channel_count.min(common::MAX_PEAK_CHANNELS))
+            .unwrap();
+        Self {
+            interval: time::Duration::from_secs_f64(1.0 / f64::from(rate)),
+            last_frame: None,
+            main: 0,
+            channels,
+            holds: Vec::new(),
+            sent: None,
+        }
+    }
+
+    pub fn update(&mut self, ch: common::Channel, reading: Reading) {
+        let level = common::quantize_level(reading.level);
+        match ch {
+            common::Channel::Main => self.main = level,
+            ch => match self.channels.get_mut(ch.to_index()) {
+                Some(channel) => *channel = level,
+                // the mixer does not have this channel
+                None => return,
+            },
+        }
+        if let Some(hold) = reading.hold {
+            self.holds.retain(|(other, _)| *other != ch);
+            self.holds.push((ch, hold));
+        }
+    }
+
+    /// The messages to send if the next frame is due, nothing if no level changed.
+    pub fn poll(&mut self, now: time::Instant) -> Vec<common::HostMessage> {
+        let due = self
+            .last_frame
+            .map(|last| now.saturating_duration_since(last) >= self.interval)
+            .unwrap_or(true);
+        if !due {
+            return Vec::new();
+        }
+        self.last_frame = Some(now);
+
+        let mut messages = Vec::new();
+        let levels = (self.main, self.channels.clone());
+        if self.sent.as_ref() != Some(&levels) {
+            messages.push(common::HostMessage::UpdatePeaks(levels.0, levels.1.clone()));
+            self.sent = Some(levels);
+        }
+        messages.extend(
+            self.holds
+                .drain(..)
+                .map(|(ch, hold)| common::HostMessage::SetPeakHold(ch, hold)),
+        );
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meter_maps_dbfs_and_holds_peaks() {
+        let mut meter = Meter::new(config::Meter {
+            range: (-60.0, 0.0),
+            attack: 0.0,
+            release: 100.0,
+            peak_hold: 1000.0,
+        });
+        let start = time::Instant::now();
+        let at = |ms| start + time::Duration::from_millis(ms);
+
+        // -20 dBFS is two thirds up the meter, rising levels are shown right away
+        let reading = meter.update(0.1, at(0));
+        assert!((reading.level - 2.0 / 3.0).abs() < 1e-4);
+        assert_eq!(reading.hold, Some(reading.level));
+
+        // falling levels decay with the release time, the hold stays...
+        let reading = meter.update(0.0, at(100));
+        assert!((reading.level - 2.0 / 3.0 / std::f32::consts::E).abs() < 1e-4);
+        assert_eq!(reading.hold, None);
+
+        // ...until it expires
+        let reading = meter.update(0.0, at(1100));
+        assert_eq!(reading.hold, Some(reading.level));
+        assert!(reading.level < 0.01);
+    }
+
+    #[test]
+    fn frames_are_rate_limited_and_skipped_without_changes() {
+        let mut frames = Frames::new(10.0, 2);
+        let start = time::Instant::now();
+        let at = |ms| start + time::Duration::from_millis(ms);
+        let reading = |level| Reading { level, hold: None };
+        let peaks = |levels: &[u8]| common::Peaks::from_slice(levels).unwrap();
+
+        // the first frame puts the mixer into a known state
+        assert_eq!(
+            frames.poll(at(0)),
+            [common::HostMessage::UpdatePeaks(0, peaks(&[0, 0]))]
+        );
+
+        frames.update(common::Channel::App(1), reading(1.0));
+        frames.update(common::Channel::App(5), reading(1.0));
+        assert_eq!(frames.poll(at(50)), []);
+        frames.update(
+            common::Channel::Main,
+            Reading {
+                level: 0.5,
+                hold: Some(0.5),
+            },
+        );
+        assert_eq!(
+            frames.poll(at(100)),
+            [
+                common::HostMessage::UpdatePeaks(128, peaks(&[0, 255])),
+                common::HostMessage::SetPeakHold(common::Channel::Main, 0.5),
+            ]
+        );
+
+        frames.update(common::Channel::App(1), reading(1.0));
+        assert_eq!(frames.poll(at(200)), []);
+    }
+}
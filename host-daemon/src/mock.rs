@@ -0,0 +1,324 @@
+//! Scriptable in-memory backend and mixer device for driving the daemon logic in tests.
+use crate::backend::{self, Event, StreamId, StreamInfo};
+use crate::device;
+use std::cell::{Cell, RefCell};
+use std::collections;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+/// An operation the mixer performed on the mock backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    SetVolume(StreamId, Vec<f32>),
+    SetMute(StreamId, bool),
+    DefaultSink(String),
+}
+
+struct StreamHandle {
+    connected_channel: Rc<Cell<Option<(common::Channel, usize)>>>,
+    peaks: Rc<RefCell<collections::VecDeque<f32>>>,
+}
+
+pub struct MockBackend {
+    events_rx: Option<mpsc::Receiver<Event<MockStream>>>,
+    events_tx: mpsc::Sender<Event<MockStream>>,
+    /// The sinks, sink-inputs, sources and source-outputs which currently exist.
+    objects: Vec<StreamInfo>,
+    /// Requested streams, delivered on the next [`backend::Backend::iterate`] like a real audio
+    /// server would.
+    requested: Vec<Event<MockStream>>,
+    /// Handles for all streams which were handed out, to inject peak data later.
+    streams: collections::HashMap<StreamId, StreamHandle>,
+    /// All operations the mixer performed, in order.
+    pub operations: Vec<Operation>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        let (events_tx, events_rx) = mpsc::channel();
+        Self {
+            events_rx: Some(events_rx),
+            events_tx,
+            objects: Vec::new(),
+            requested: Vec::new(),
+            streams: collections::HashMap::new(),
+            operations: Vec::new(),
+        }
+    }
+
+    /// Build a stereo sink-input at 100% volume with the given properties.
+    pub fn sink_input(
+        index: u32,
+        properties: &[(&str, &str)],
+        mute: bool,
+    ) -> backend::SinkInputInfo {
+        let properties: backend::Properties = properties
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        backend::SinkInputInfo {
+            index,
+            name: properties.get("media.name").cloned(),
+            application: properties.get("application.name").cloned(),
+            connected_sink: 0,
+            properties,
+            volume: vec![1.0, 1.0],
+            mute,
+        }
+    }
+
+    /// Let an object show up on the audio server.
+    pub fn add(&mut self, info: StreamInfo) {
+        self.objects.push(info.clone());
+        self.send(added_event(info));
+    }
+
+    /// Change an existing object, e.g. its properties or volume.
+    pub fn change(&mut self, info: StreamInfo) {
+        let object = self
+            .objects
+            .iter_mut()
+            .find(|object| object.id() == info.id())
+            .expect("no such object");
+        *object = info.clone();
+        self.send(match info {
+            StreamInfo::Sink(info) => Event::SinkChanged(info),
+            StreamInfo::SinkInput(info) => Event::SinkInputChanged(info),
+            StreamInfo::Source(info) => Event::SourceChanged(info),
+            StreamInfo::SourceOutput(info) => Event::SourceOutputChanged(info),
+        });
+    }
+
+    /// Remove an object from the audio server.
+    pub fn remove(&mut self, id: StreamId) {
+        self.objects.retain(|object| object.id() != id);
+        self.send(match id {
+            StreamId::Sink(index) => Event::SinkRemoved(index),
+            StreamId::SinkInput(index) => Event::SinkInputRemoved(index),
+            StreamId::Source(index) => Event::SourceRemoved(index),
+            StreamId::SourceOutput(index) => Event::SourceOutputRemoved(index),
+        });
+    }
+
+    fn send(&self, event: Event<MockStream>) {
+        self.events_tx.send(event).expect("event channel error");
+    }
+
+    /// Queue a peak sample for the stream and notify the channel it is connected to.
+    pub fn push_peak(&mut self, id: StreamId, peak: f32) {
+        let handle = self
+            .streams
+            .get(&id)
+            .expect("no stream was requested for this id");
+        handle.peaks.borrow_mut().push_back(peak);
+        if let Some((ch, index)) = handle.connected_channel.get() {
+            self.events_tx
+                .send(Event::NewPeakData(ch, index))
+                .expect("event channel error");
+        }
+    }
+
+    fn new_stream(&mut self, info: StreamInfo) -> MockStream {
+        let stream = MockStream {
+            info,
+            connected: false,
+            connected_channel: Rc::new(Cell::new(None)),
+            peaks: Rc::new(RefCell::new(collections::VecDeque::new())),
+        };
+        self.streams.insert(
+            stream.info.id(),
+            StreamHandle {
+                connected_channel: stream.connected_channel.clone(),
+                peaks: stream.peaks.clone(),
+            },
+        );
+        stream
+    }
+}
+
+impl backend::Backend for MockBackend {
+    type Stream = MockStream;
+
+    fn take_event_receiver(&mut self) -> Option<mpsc::Receiver<Event<MockStream>>> {
+        self.events_rx.take()
+    }
+
+    fn iterate(&mut self, _block: bool) -> anyhow::Result<()> {
+        for event in std::mem::take(&mut self.requested) {
+            self.send(event);
+        }
+        Ok(())
+    }
+
+    fn rescan(&mut self) {
+        for info in self.objects.iter() {
+            self.send(added_event(info.clone()));
+        }
+    }
+
+    fn request_sink_input_stream(
+        &mut self,
+        info: backend::SinkInputInfo,
+        for_channel: common::Channel,
+    ) {
+        let stream = self.new_stream(StreamInfo::SinkInput(info));
+        self.requested
+            .push(Event::NewSinkInput(for_channel, stream));
+    }
+
+    fn request_sink_stream(
+        &mut self,
+        info: backend::SinkInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = self.new_stream(StreamInfo::Sink(info));
+        self.requested.push(Event::NewSink(for_channel, stream));
+        Ok(())
+    }
+
+    fn request_source_stream(
+        &mut self,
+        info: backend::SourceInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = self.new_stream(StreamInfo::Source(info));
+        self.requested.push(Event::NewSource(for_channel, stream));
+        Ok(())
+    }
+
+    fn request_source_output_stream(
+        &mut self,
+        info: backend::SourceOutputInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = self.new_stream(StreamInfo::SourceOutput(info));
+        self.requested
+            .push(Event::NewSourceOutput(for_channel, stream));
+        Ok(())
+    }
+
+    fn set_default_sink(&mut self, name: &str) {
+        self.operations
+            .push(Operation::DefaultSink(name.to_owned()));
+    }
+}
+
+fn added_event(info: StreamInfo) -> Event<MockStream> {
+    match info {
+        StreamInfo::Sink(info) => Event::SinkAdded(info),
+        StreamInfo::SinkInput(info) => Event::SinkInputAdded(info),
+        StreamInfo::Source(info) => Event::SourceAdded(info),
+        StreamInfo::SourceOutput(info) => Event::SourceOutputAdded(info),
+    }
+}
+
+#[derive(Debug)]
+pub struct MockStream {
+    info: StreamInfo,
+    connected: bool,
+    connected_channel: Rc<Cell<Option<(common::Channel, usize)>>>,
+    peaks: Rc<RefCell<collections::VecDeque<f32>>>,
+}
+
+impl backend::Stream for MockStream {
+    type Backend = MockBackend;
+
+    fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    fn info_mut(&mut self) -> &mut StreamInfo {
+        &mut self.info
+    }
+
+    fn connect(&mut self) -> anyhow::Result<()> {
+        self.connected = true;
+        Ok(())
+    }
+
+    fn set_connected_channel(&self, ch: common::Channel, index: usize) {
+        self.connected_channel.set(Some((ch, index)));
+    }
+
+    fn get_recent_peak(&mut self) -> anyhow::Result<Option<f32>> {
+        anyhow::ensure!(self.connected, "stream was never connected");
+        Ok(self
+            .peaks
+            .borrow_mut()
+            .drain(..)
+            .fold(None, |max, peak: f32| Some(peak.max(max.unwrap_or(0.0)))))
+    }
+
+    fn apply_volume(&mut self, backend: &mut MockBackend) {
+        backend.operations.push(Operation::SetVolume(
+            self.info.id(),
+            self.info.volume().to_vec(),
+        ));
+    }
+
+    fn set_mute(&mut self, backend: &mut MockBackend, mute: bool) {
+        self.info.set_muted(mute);
+        backend
+            .operations
+            .push(Operation::SetMute(self.info.id(), mute));
+    }
+}
+
+/// A mixer which records everything the daemon sends to it.
+pub struct MockDevice {
+    info: common::DeviceInfo,
+    /// Messages waiting to be received by the daemon.
+    pub incoming: collections::VecDeque<common::DeviceMessage>,
+    /// All messages the daemon sent, in order.
+    pub sent: Vec<common::HostMessage>,
+}
+
+impl MockDevice {
+    /// A mixer with a display and the given number of application channels.
+    pub fn new(channel_count: u8) -> Self {
+        Self {
+            info: common::DeviceInfo {
+                protocol_version: common::PROTOCOL_VERSION,
+                channel_count,
+                capabilities: common::Capabilities {
+                    display: true,
+                    icons: true,
+                },
+                ..Default::default()
+            },
+            incoming: collections::VecDeque::new(),
+            sent: Vec::new(),
+        }
+    }
+}
+
+impl device::Device for MockDevice {
+    fn info(&self) -> &common::DeviceInfo {
+        &self.info
+    }
+
+    fn send(&mut self, msg: common::HostMessage) -> anyhow::Result<()> {
+        self.sent.push(msg);
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> anyhow::Result<Option<common::DeviceMessage>> {
+        Ok(self.incoming.pop_front())
+    }
+
+    fn send_icon(&mut self, _ch: common::Channel, _icon_data: &[u8]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn send_label(&mut self, ch: common::Channel, label: &str) -> anyhow::Result<()> {
+        self.send(common::HostMessage::SetLabel(ch, common::make_label(label)))
+    }
+
+    fn handle_icon_status(
+        &mut self,
+        _sequence: u16,
+        _status: common::IconStatus,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
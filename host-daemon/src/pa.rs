@@ -1,6 +1,8 @@
 #![allow(unused_variables, dead_code)]
 
-use crate::config;
+use crate::backend::{
+    self, Backend as _, SinkInfo, SinkInputInfo, SourceInfo, SourceOutputInfo, StreamId, StreamInfo,
+};
 use anyhow::Context;
 use pulse::callbacks::ListResult;
 use pulse::context;
@@ -16,105 +18,84 @@ const SAMPLE_SPEC: pulse::sample::Spec = pulse::sample::Spec {
     rate: 25,
 };
 
-pub struct SinkInputInfo {
-    pub index: u32,
-    pub name: Option<String>,
-    pub application: Option<String>,
-    connected_sink: u32,
-    pub properties: pulse::proplist::Proplist,
-    volume: pulse::volume::ChannelVolumes,
-    mute: bool,
+/// Events pushed by the PulseAudio backend.
+pub type Event = backend::Event<Stream>;
+
+fn properties_from_pa(proplist: &pulse::proplist::Proplist) -> backend::Properties {
+    proplist
+        .iter()
+        .map(|key| {
+            let value = proplist
+                .get_str(&key)
+                .expect("missing property for iterated key");
+            (key, value)
+        })
+        .collect()
 }
 
-impl SinkInputInfo {
-    fn from_pa(info: &context::introspect::SinkInputInfo) -> Self {
-        Self {
-            index: info.index,
-            name: info.name.as_ref().map(|c| c.to_owned().into_owned()),
-            application: info
-                .proplist
-                .get_str(pulse::proplist::properties::APPLICATION_NAME),
-            connected_sink: info.sink,
-            properties: info.proplist.clone(),
-            volume: info.volume.clone(),
-            mute: info.mute,
-        }
-    }
+fn volume_from_pa(volume: &pulse::volume::ChannelVolumes) -> Vec<f32> {
+    volume
+        .get()
+        .iter()
+        .map(|v| v.0 as f32 / pulse::volume::Volume::NORMAL.0 as f32)
+        .collect()
 }
 
-impl std::fmt::Debug for SinkInputInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut propmap = std::collections::BTreeMap::new();
-        for key in self.properties.iter() {
-            let value = self
-                .properties
-                .get_str(&key)
-                .expect("missing property for iterated key");
-            propmap.insert(key, value);
-        }
-        f.debug_struct("SinkInputInfo")
-            .field("index", &self.index)
-            .field("name", &self.name)
-            .field("application", &self.application)
-            .field("connected_sink", &self.connected_sink)
-            .field("properties", &propmap)
-            .field("volume", &self.volume.avg().print_verbose(true))
-            .field("mute", &self.mute)
-            .finish()
+fn volume_to_pa(volume: &[f32]) -> pulse::volume::ChannelVolumes {
+    let mut pa_volume = pulse::volume::ChannelVolumes::default();
+    pa_volume.set_len(volume.len() as u8);
+    for (pa_v, v) in pa_volume.get_mut().iter_mut().zip(volume) {
+        *pa_v = pulse::volume::Volume((pulse::volume::Volume::NORMAL.0 as f32 * v) as u32);
     }
+    pa_volume
 }
 
-pub struct SinkInfo {
-    index: u32,
-    name: Option<String>,
-    monitoring_source: u32,
-    volume: pulse::volume::ChannelVolumes,
-    mute: bool,
+fn sink_input_info_from_pa(info: &context::introspect::SinkInputInfo) -> SinkInputInfo {
+    SinkInputInfo {
+        index: info.index,
+        name: info.name.as_ref().map(|c| c.to_owned().into_owned()),
+        application: info
+            .proplist
+            .get_str(pulse::proplist::properties::APPLICATION_NAME),
+        connected_sink: info.sink,
+        properties: properties_from_pa(&info.proplist),
+        volume: volume_from_pa(&info.volume),
+        mute: info.mute,
+    }
 }
 
-impl SinkInfo {
-    fn from_pa(info: &context::introspect::SinkInfo) -> Self {
-        Self {
-            index: info.index,
-            name: info.name.as_ref().map(|c| c.to_owned().into_owned()),
-            monitoring_source: info.monitor_source,
-            volume: info.volume.clone(),
-            mute: info.mute,
-        }
+fn source_output_info_from_pa(info: &context::introspect::SourceOutputInfo) -> SourceOutputInfo {
+    SourceOutputInfo {
+        index: info.index,
+        name: info.name.as_ref().map(|c| c.to_owned().into_owned()),
+        application: info
+            .proplist
+            .get_str(pulse::proplist::properties::APPLICATION_NAME),
+        connected_source: info.source,
+        properties: properties_from_pa(&info.proplist),
+        volume: volume_from_pa(&info.volume),
+        mute: info.mute,
     }
 }
 
-impl std::fmt::Debug for SinkInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("SinkInfo")
-            .field("index", &self.index)
-            .field("name", &self.name)
-            .field("monitoring_source", &self.monitoring_source)
-            .field("volume", &self.volume.avg().print_verbose(true))
-            .field("mute", &self.mute)
-            .finish()
+fn sink_info_from_pa(info: &context::introspect::SinkInfo) -> SinkInfo {
+    SinkInfo {
+        index: info.index,
+        name: info.name.as_ref().map(|c| c.to_owned().into_owned()),
+        monitoring_source: info.monitor_source,
+        volume: volume_from_pa(&info.volume),
+        mute: info.mute,
     }
 }
 
-#[derive(Debug)]
-pub enum Event {
-    /// After querying the default sink, PulseAudio came back with this stream.
-    NewDefaultSink(Stream),
-    /// A new sink-input showed up and we need to check whether it matches any of our channels - if
-    /// yes, it should be attached.
-    SinkInputAdded(SinkInputInfo),
-    /// Some property of a sink-input changed.  It potentially needs to be re-attached to a
-    /// different channel.
-    SinkInputChanged(SinkInputInfo),
-    /// A sink-input was removed and we should probably drop it from a potentially connected
-    /// channel as well.
-    SinkInputRemoved(u32),
-    /// A new sink-input stream is available for the given channel.
-    NewSinkInput(common::Channel, Stream),
-    /// New signal peak information is available for this stream (sink / sink-input).
-    NewPeakData(common::Channel, usize),
-    // /// An error occurred asynchronously and we need to abort.
-    // FatalError(anyhow::Error),
+fn source_info_from_pa(info: &context::introspect::SourceInfo) -> SourceInfo {
+    SourceInfo {
+        index: info.index,
+        name: info.name.as_ref().map(|c| c.to_owned().into_owned()),
+        properties: properties_from_pa(&info.proplist),
+        volume: volume_from_pa(&info.volume),
+        mute: info.mute,
+    }
 }
 
 #[derive(Debug)]
@@ -128,6 +109,12 @@ enum InternalEvent {
     DefaultSinkName(String),
     /// We got information about the default sink - enough to create a stream for it.
     SinkData(SinkInfo),
+    /// A new sink was detected - we should query its information and tell the application about
+    /// it.
+    SinkPending(u32),
+    /// A sink change was detected - we should query its information and tell the application
+    /// about the change.
+    SinkChangePending(u32),
     /// A new sink-input was detected - we should query its information and tell the application
     /// about it.
     SinkInputPending(u32),
@@ -140,6 +127,18 @@ enum InternalEvent {
         for_channel: common::Channel,
         monitor_source: u32,
     },
+    /// A new source was detected - we should query its information and tell the application
+    /// about it.
+    SourcePending(u32),
+    /// A source change was detected - we should query its information and tell the application
+    /// about the change.
+    SourceChangePending(u32),
+    /// A new source-output was detected - we should query its information and tell the
+    /// application about it.
+    SourceOutputPending(u32),
+    /// A source-output change was detected - we should query its information and tell the
+    /// application about the change.
+    SourceOutputChangePending(u32),
 }
 
 /// Interface for interacting with Pulseaudio.
@@ -159,6 +158,8 @@ pub struct PulseInterface {
 
     /// Name of the current default sink (used to check if it changed).
     current_default_sink: Option<String>,
+    /// Our own client index, to ignore the source-outputs of our monitoring streams.
+    own_client: Option<u32>,
 }
 
 impl PulseInterface {
@@ -194,6 +195,7 @@ impl PulseInterface {
         }
 
         let introspector = context.introspect();
+        let own_client = context.get_index();
 
         let (external_tx, external_rx) = mpsc::channel();
         let (internal_tx, internal_rx) = mpsc::channel();
@@ -210,13 +212,25 @@ impl PulseInterface {
                 let facility = facility.expect("invalid subscribe callback params");
 
                 match (facility, op) {
-                    (Facility::Sink, Operation::New) => internal_tx
-                        .send(InternalEvent::SinkUpdateNeeded)
-                        .expect("event channel error"),
-                    (Facility::Sink, Operation::Removed) => internal_tx
-                        .send(InternalEvent::SinkUpdateNeeded)
+                    (Facility::Sink, Operation::New) => {
+                        internal_tx
+                            .send(InternalEvent::SinkUpdateNeeded)
+                            .expect("event channel error");
+                        internal_tx
+                            .send(InternalEvent::SinkPending(index))
+                            .expect("event channel error");
+                    }
+                    (Facility::Sink, Operation::Removed) => {
+                        internal_tx
+                            .send(InternalEvent::SinkUpdateNeeded)
+                            .expect("event channel error");
+                        external_tx
+                            .send(Event::SinkRemoved(index))
+                            .expect("event channel error");
+                    }
+                    (Facility::Sink, Operation::Changed) => internal_tx
+                        .send(InternalEvent::SinkChangePending(index))
                         .expect("event channel error"),
-                    (Facility::Sink, Operation::Changed) => (), // ignore
                     (Facility::Server, _) => internal_tx
                         .send(InternalEvent::SinkUpdateNeeded) // default sink might have changed
                         .expect("event channel error"),
@@ -229,6 +243,24 @@ impl PulseInterface {
                     (Facility::SinkInput, Operation::Changed) => internal_tx
                         .send(InternalEvent::SinkInputChangePending(index))
                         .expect("event channel error"),
+                    (Facility::Source, Operation::New) => internal_tx
+                        .send(InternalEvent::SourcePending(index))
+                        .expect("event channel error"),
+                    (Facility::Source, Operation::Removed) => external_tx
+                        .send(Event::SourceRemoved(index))
+                        .expect("event channel error"),
+                    (Facility::Source, Operation::Changed) => internal_tx
+                        .send(InternalEvent::SourceChangePending(index))
+                        .expect("event channel error"),
+                    (Facility::SourceOutput, Operation::New) => internal_tx
+                        .send(InternalEvent::SourceOutputPending(index))
+                        .expect("event channel error"),
+                    (Facility::SourceOutput, Operation::Removed) => external_tx
+                        .send(Event::SourceOutputRemoved(index))
+                        .expect("event channel error"),
+                    (Facility::SourceOutput, Operation::Changed) => internal_tx
+                        .send(InternalEvent::SourceOutputChangePending(index))
+                        .expect("event channel error"),
                     _ => unreachable!("unexpected facility: {:?}", facility),
                 };
             }))
@@ -238,10 +270,16 @@ impl PulseInterface {
         // - SINK: if the available sinks (= output devices) change
         // - SINK_INPUT: if the playing audio sources change
         // - SERVER: if the selected default sink (output device) changes
+        // - SOURCE: if input devices (microphones) change or are muted
+        // - SOURCE_OUTPUT: if the recording applications change
         {
             use pulse::context::subscribe::InterestMaskSet;
             context.subscribe(
-                InterestMaskSet::SINK | InterestMaskSet::SINK_INPUT | InterestMaskSet::SERVER,
+                InterestMaskSet::SINK
+                    | InterestMaskSet::SINK_INPUT
+                    | InterestMaskSet::SERVER
+                    | InterestMaskSet::SOURCE
+                    | InterestMaskSet::SOURCE_OUTPUT,
                 |_| (),
             );
         }
@@ -252,22 +290,6 @@ impl PulseInterface {
             .send(InternalEvent::SinkUpdateNeeded)
             .expect("event channel error");
 
-        // ...and "adding" all currently existing sink-inputs.
-        let done = Rc::new(Cell::new(Ok(false)));
-        introspector.get_sink_input_info_list({
-            let external_tx = external_tx.clone();
-            let done = done.clone();
-            move |result| match result {
-                ListResult::Item(info) => {
-                    external_tx
-                        .send(Event::SinkInputAdded(SinkInputInfo::from_pa(info)))
-                        .expect("event channel error");
-                }
-                ListResult::Error => done.set(Err(anyhow::anyhow!("pulseaudio list error"))),
-                ListResult::End => done.set(Ok(true)),
-            }
-        });
-
         let mut this = Self {
             mainloop,
             context,
@@ -278,8 +300,11 @@ impl PulseInterface {
             internal_tx,
 
             current_default_sink: None,
+            own_client,
         };
 
+        // ...and "adding" all currently existing sinks, sources, source-outputs and sink-inputs.
+        let done = this.list_all();
         'add_all_sink_inputs: loop {
             this.iterate(true)?;
             // returns `true` once we hit the end of the sink-input list
@@ -291,88 +316,77 @@ impl PulseInterface {
         Ok(this)
     }
 
-    fn iterate_mainloop(mainloop: &mut mainloop::Mainloop, block: bool) -> anyhow::Result<()> {
-        match mainloop.iterate(block) {
-            mainloop::IterateResult::Success(_) => Ok(()),
-            mainloop::IterateResult::Quit(_) => unreachable!("no code should quit the mainloop!"),
-            mainloop::IterateResult::Err(e) => Err(e).context("failed mainloop iteration"),
-        }
-    }
-
-    pub fn take_event_receiver(&mut self) -> Option<mpsc::Receiver<Event>> {
-        self.external_rx.take()
-    }
-
-    pub fn iterate(&mut self, block: bool) -> anyhow::Result<()> {
-        Self::iterate_mainloop(&mut self.mainloop, block)?;
-        while let Ok(event) = self.internal_rx.try_recv() {
-            match event {
-                InternalEvent::SinkUpdateNeeded => self.query_default_sink(),
-                InternalEvent::DefaultSinkName(name) => {
-                    if self.current_default_sink.as_ref() != Some(&name) {
-                        // the name differs from the previous one - we need to issue an update
-                        self.query_sink_data(&name);
-                    }
-                }
-                InternalEvent::SinkData(info) => {
-                    // Create a new stream and pass it to the application
-                    let stream = Stream::new_for_sink(self, info)
-                        .context("failed creating monitoring stream for default sink")?;
-                    self.external_tx
-                        .send(Event::NewDefaultSink(stream))
+    /// Announce all existing sinks, sources, source-outputs and sink-inputs as `*Added` events.
+    ///
+    /// The returned cell turns `Ok(true)` once the sink-input list (the last one) is complete.
+    fn list_all(&self) -> Rc<Cell<anyhow::Result<bool>>> {
+        self.introspector.get_sink_info_list({
+            let external_tx = self.external_tx.clone();
+            move |result| match result {
+                ListResult::Item(info) => {
+                    external_tx
+                        .send(Event::SinkAdded(sink_info_from_pa(info)))
                         .expect("event channel error");
                 }
-                InternalEvent::SinkInputPending(index) => self.query_added_sink_input(index),
-                InternalEvent::SinkInputChangePending(index) => {
-                    self.query_changed_sink_input(index)
-                }
-                InternalEvent::RequestSinkInputStream {
-                    input_info,
-                    for_channel,
-                    monitor_source,
-                } => {
-                    let stream = Stream::new_for_sink_input(self, input_info, monitor_source)?;
-                    self.external_tx
-                        .send(Event::NewSinkInput(for_channel, stream))
+                ListResult::Error => log::warn!("error while listing sinks - ignoring"),
+                ListResult::End => (),
+            }
+        });
+
+        self.introspector.get_source_info_list({
+            let external_tx = self.external_tx.clone();
+            move |result| match result {
+                ListResult::Item(info) => {
+                    external_tx
+                        .send(Event::SourceAdded(source_info_from_pa(info)))
                         .expect("event channel error");
                 }
+                ListResult::Error => log::warn!("error while listing sources - ignoring"),
+                ListResult::End => (),
             }
-        }
-        Ok(())
-    }
+        });
 
-    /// Request a stream for a sink-input.
-    ///
-    /// This will first query the sink-input's sink to get its monitoring source.
-    ///
-    /// Once received, the stream will be pushed as an event.
-    pub fn request_sink_input_stream(
-        &mut self,
-        input_info: SinkInputInfo,
-        for_channel: common::Channel,
-    ) {
-        let connected_sink = input_info.connected_sink;
-        let mut input_info = Some(input_info);
-        self.introspector.get_sink_info_by_index(connected_sink, {
-            let internal_tx = self.internal_tx.clone();
+        self.introspector.get_source_output_info_list({
+            let external_tx = self.external_tx.clone();
+            let own_client = self.own_client;
             move |result| match result {
                 ListResult::Item(info) => {
-                    internal_tx
-                        .send(InternalEvent::RequestSinkInputStream {
-                            input_info: input_info.take().expect(
-                                "callback for request_sink_input_stream() called too often",
-                            ),
-                            for_channel,
-                            monitor_source: info.monitor_source,
-                        })
+                    if info.client.is_some() && info.client == own_client {
+                        return;
+                    }
+                    external_tx
+                        .send(Event::SourceOutputAdded(source_output_info_from_pa(info)))
                         .expect("event channel error");
                 }
+                ListResult::Error => log::warn!("error while listing source-outputs - ignoring"),
                 ListResult::End => (),
-                ListResult::Error => {
-                    log::warn!("error while querying sink data - ignoring this sink")
+            }
+        });
+
+        let done = Rc::new(Cell::new(Ok(false)));
+        self.introspector.get_sink_input_info_list({
+            let external_tx = self.external_tx.clone();
+            let done = done.clone();
+            move |result| match result {
+                ListResult::Item(info) => {
+                    external_tx
+                        .send(Event::SinkInputAdded(sink_input_info_from_pa(info)))
+                        .expect("event channel error");
                 }
+                ListResult::Error => done.set(Err(anyhow::anyhow!("pulseaudio list error"))),
+                ListResult::End => done.set(Ok(true)),
             }
         });
+
+        done
+    }
+
+    fn iterate_mainloop(mainloop: &mut mainloop::Mainloop, block: bool) -> anyhow::Result<()> {
+        match mainloop.iterate(block) {
+            mainloop::IterateResult::Success(_) => Ok(()),
+            mainloop::IterateResult::Quit(_) => unreachable!("no code should quit the mainloop!"),
+            mainloop::IterateResult::Err(e) => Err(e).context("failed mainloop iteration"),
+        }
     }
 
     /// Query the default sink.
@@ -400,7 +414,7 @@ impl PulseInterface {
             move |result| match result {
                 ListResult::Item(info) => {
                     internal_tx
-                        .send(InternalEvent::SinkData(SinkInfo::from_pa(info)))
+                        .send(InternalEvent::SinkData(sink_info_from_pa(info)))
                         .expect("event channel error");
                 }
                 ListResult::End => (),
@@ -411,6 +425,26 @@ impl PulseInterface {
         });
     }
 
+    /// Query a sink.
+    ///
+    /// Triggers the event built by `make_event` on completion.
+    fn query_sink(&mut self, index: u32, make_event: fn(SinkInfo) -> Event) {
+        self.introspector.get_sink_info_by_index(index, {
+            let external_tx = self.external_tx.clone();
+            move |result| match result {
+                ListResult::Item(info) => {
+                    external_tx
+                        .send(make_event(sink_info_from_pa(info)))
+                        .expect("event channel error");
+                }
+                ListResult::Error => {
+                    log::debug!("Error while querying sink {} - ignoring.", index)
+                }
+                ListResult::End => (),
+            }
+        });
+    }
+
     /// Query a newly added sink-input.
     ///
     /// Triggers [`Event::SinkInputAdded`] on completion.
@@ -420,7 +454,7 @@ impl PulseInterface {
             move |result| match result {
                 ListResult::Item(info) => {
                     external_tx
-                        .send(Event::SinkInputAdded(SinkInputInfo::from_pa(info)))
+                        .send(Event::SinkInputAdded(sink_input_info_from_pa(info)))
                         .expect("event channel error");
                 }
                 ListResult::Error => {
@@ -440,7 +474,7 @@ impl PulseInterface {
             move |result| match result {
                 ListResult::Item(info) => {
                     external_tx
-                        .send(Event::SinkInputChanged(SinkInputInfo::from_pa(info)))
+                        .send(Event::SinkInputChanged(sink_input_info_from_pa(info)))
                         .expect("event channel error");
                 }
                 ListResult::Error => {
@@ -450,43 +484,214 @@ impl PulseInterface {
             }
         });
     }
-}
 
-impl Drop for PulseInterface {
-    fn drop(&mut self) {
-        // SAFETY: Not doing this causes a segfault /o\
-        self.context.disconnect();
+    /// Query a source.
+    ///
+    /// Triggers the event built by `make_event` on completion.
+    fn query_source(&mut self, index: u32, make_event: fn(SourceInfo) -> Event) {
+        self.introspector.get_source_info_by_index(index, {
+            let external_tx = self.external_tx.clone();
+            move |result| match result {
+                ListResult::Item(info) => {
+                    external_tx
+                        .send(make_event(source_info_from_pa(info)))
+                        .expect("event channel error");
+                }
+                ListResult::Error => {
+                    log::debug!("Error while querying source {} - ignoring.", index)
+                }
+                ListResult::End => (),
+            }
+        });
     }
-}
 
-#[derive(Debug)]
-enum StreamInfo {
-    Sink(SinkInfo),
-    SinkInput(SinkInputInfo),
+    /// Query a source-output.
+    ///
+    /// Triggers the event built by `make_event` on completion, unless the source-output belongs
+    /// to one of our own monitoring streams.
+    fn query_source_output(&mut self, index: u32, make_event: fn(SourceOutputInfo) -> Event) {
+        self.introspector.get_source_output_info(index, {
+            let external_tx = self.external_tx.clone();
+            let own_client = self.own_client;
+            move |result| match result {
+                ListResult::Item(info) => {
+                    if info.client.is_some() && info.client == own_client {
+                        return;
+                    }
+                    external_tx
+                        .send(make_event(source_output_info_from_pa(info)))
+                        .expect("event channel error");
+                }
+                ListResult::Error => {
+                    log::debug!("Error while querying source-output {} - ignoring.", index)
+                }
+                ListResult::End => (),
+            }
+        });
+    }
 }
 
-impl StreamInfo {
-    fn description(&self) -> String {
-        match self {
-            StreamInfo::Sink(s) => format!("Sink '{}'", s.name.as_deref().unwrap_or("unknown")),
-            StreamInfo::SinkInput(s) => {
-                format!("Sink-Input '{}'", s.name.as_deref().unwrap_or("unknown"))
+impl backend::Backend for PulseInterface {
+    type Stream = Stream;
+
+    fn take_event_receiver(&mut self) -> Option<mpsc::Receiver<Event>> {
+        self.external_rx.take()
+    }
+
+    fn iterate(&mut self, block: bool) -> anyhow::Result<()> {
+        Self::iterate_mainloop(&mut self.mainloop, block)?;
+        while let Ok(event) = self.internal_rx.try_recv() {
+            match event {
+                InternalEvent::SinkUpdateNeeded => self.query_default_sink(),
+                InternalEvent::DefaultSinkName(name) => {
+                    if self.current_default_sink.as_ref() != Some(&name) {
+                        // the name differs from the previous one - we need to issue an update
+                        self.query_sink_data(&name);
+                    }
+                }
+                InternalEvent::SinkData(info) => {
+                    // Create a new stream and pass it to the application
+                    let stream = Stream::new_for_sink(self, info)
+                        .context("failed creating monitoring stream for default sink")?;
+                    self.external_tx
+                        .send(Event::NewDefaultSink(stream))
+                        .expect("event channel error");
+                }
+                InternalEvent::SinkPending(index) => self.query_sink(index, Event::SinkAdded),
+                InternalEvent::SinkChangePending(index) => {
+                    self.query_sink(index, Event::SinkChanged)
+                }
+                InternalEvent::SinkInputPending(index) => self.query_added_sink_input(index),
+                InternalEvent::SinkInputChangePending(index) => {
+                    self.query_changed_sink_input(index)
+                }
+                InternalEvent::RequestSinkInputStream {
+                    input_info,
+                    for_channel,
+                    monitor_source,
+                } => {
+                    let stream = Stream::new_for_sink_input(self, input_info, monitor_source)?;
+                    self.external_tx
+                        .send(Event::NewSinkInput(for_channel, stream))
+                        .expect("event channel error");
+                }
+                InternalEvent::SourcePending(index) => self.query_source(index, Event::SourceAdded),
+                InternalEvent::SourceChangePending(index) => {
+                    self.query_source(index, Event::SourceChanged)
+                }
+                InternalEvent::SourceOutputPending(index) => {
+                    self.query_source_output(index, Event::SourceOutputAdded)
+                }
+                InternalEvent::SourceOutputChangePending(index) => {
+                    self.query_source_output(index, Event::SourceOutputChanged)
+                }
             }
         }
+        Ok(())
     }
 
-    fn volume(&mut self) -> &mut pulse::volume::ChannelVolumes {
-        match self {
-            StreamInfo::Sink(s) => &mut s.volume,
-            StreamInfo::SinkInput(s) => &mut s.volume,
-        }
+    fn rescan(&mut self) {
+        self.list_all();
     }
 
-    fn muted(&self) -> bool {
-        match self {
-            StreamInfo::Sink(s) => s.mute,
-            StreamInfo::SinkInput(s) => s.mute,
-        }
+    /// Request a stream for a sink-input.
+    ///
+    /// This will first query the sink-input's sink to get its monitoring source.
+    ///
+    /// Once received, the stream will be pushed as an event.
+    fn request_sink_input_stream(
+        &mut self,
+        input_info: SinkInputInfo,
+        for_channel: common::Channel,
+    ) {
+        let connected_sink = input_info.connected_sink;
+        let mut input_info = Some(input_info);
+        self.introspector.get_sink_info_by_index(connected_sink, {
+            let internal_tx = self.internal_tx.clone();
+            move |result| match result {
+                ListResult::Item(info) => {
+                    internal_tx
+                        .send(InternalEvent::RequestSinkInputStream {
+                            input_info: input_info.take().expect(
+                                "callback for request_sink_input_stream() called too often",
+                            ),
+                            for_channel,
+                            monitor_source: info.monitor_source,
+                        })
+                        .expect("event channel error");
+                }
+                ListResult::End => (),
+                ListResult::Error => {
+                    log::warn!("error while querying sink data - ignoring this sink")
+                }
+            }
+        });
+    }
+
+    /// Create a stream for a sink.
+    ///
+    /// The peaks are taken from the sink's monitor source, just like for the default sink.  The
+    /// stream is pushed as an [`Event::NewSink`] right away.
+    fn request_sink_stream(
+        &mut self,
+        sink_info: SinkInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = Stream::new_for_sink(self, sink_info)
+            .context("failed creating monitoring stream for sink")?;
+        self.external_tx
+            .send(Event::NewSink(for_channel, stream))
+            .expect("event channel error");
+        Ok(())
+    }
+
+    /// Create a stream for a source.
+    ///
+    /// Sources are monitored directly so no further information needs to be queried - the
+    /// stream is pushed as an [`Event::NewSource`] right away.
+    fn request_source_stream(
+        &mut self,
+        source_info: SourceInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = Stream::new_for_source(self, source_info)
+            .context("failed creating monitoring stream for source")?;
+        self.external_tx
+            .send(Event::NewSource(for_channel, stream))
+            .expect("event channel error");
+        Ok(())
+    }
+
+    /// Create a stream for a source-output.
+    ///
+    /// There is no way to monitor a source-output by itself so the peaks are taken from the
+    /// source it records from.  The stream is pushed as an [`Event::NewSourceOutput`] right away.
+    fn request_source_output_stream(
+        &mut self,
+        output_info: SourceOutputInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = Stream::new_for_source_output(self, output_info)
+            .context("failed creating monitoring stream for source-output")?;
+        self.external_tx
+            .send(Event::NewSourceOutput(for_channel, stream))
+            .expect("event channel error");
+        Ok(())
+    }
+    fn set_default_sink(&mut self, name: &str) {
+        let name_owned = name.to_owned();
+        self.context.set_default_sink(name, move |success| {
+            if !success {
+                log::warn!("failed switching the default sink to {:?}", name_owned);
+            }
+        });
+    }
+}
+
+impl Drop for PulseInterface {
+    fn drop(&mut self) {
+        // SAFETY: Not doing this causes a segfault /o\
+        self.context.disconnect();
     }
 }
 
@@ -517,6 +722,19 @@ impl Stream {
         Self::new(pa, StreamInfo::SinkInput(info), monitor_source)
     }
 
+    fn new_for_source(pa: &mut PulseInterface, info: SourceInfo) -> anyhow::Result<Self> {
+        let monitor_source = info.index;
+        Self::new(pa, StreamInfo::Source(info), monitor_source)
+    }
+
+    fn new_for_source_output(
+        pa: &mut PulseInterface,
+        info: SourceOutputInfo,
+    ) -> anyhow::Result<Self> {
+        let monitor_source = info.connected_source;
+        Self::new(pa, StreamInfo::SourceOutput(info), monitor_source)
+    }
+
     fn new(pa: &mut PulseInterface, info: StreamInfo, monitor_source: u32) -> anyhow::Result<Self> {
         let mut stream = pulse::stream::Stream::new(
             &mut pa.context,
@@ -553,8 +771,20 @@ impl Stream {
             monitor_source,
         })
     }
+}
+
+impl backend::Stream for Stream {
+    type Backend = PulseInterface;
+
+    fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    fn info_mut(&mut self) -> &mut StreamInfo {
+        &mut self.info
+    }
 
-    pub fn connect(&mut self) -> anyhow::Result<()> {
+    fn connect(&mut self) -> anyhow::Result<()> {
         // TODO: do DONT_INHIBIT_AUTO_SUSPEND and DONT_MOVE properly
         let mut flags =
             pulse::stream::FlagSet::PEAK_DETECT | pulse::stream::FlagSet::ADJUST_LATENCY;
@@ -576,25 +806,11 @@ impl Stream {
         Ok(())
     }
 
-    pub fn set_connected_channel(&self, ch: common::Channel, index: usize) {
+    fn set_connected_channel(&self, ch: common::Channel, index: usize) {
         self.connected_channel.set(Some((ch, index)));
     }
 
-    pub fn is_for_sink_input(&self, sink_input: u32) -> bool {
-        match &self.info {
-            StreamInfo::Sink(_) => false,
-            StreamInfo::SinkInput(info) => info.index == sink_input,
-        }
-    }
-
-    pub fn sink_input_index(&self) -> Option<u32> {
-        match &self.info {
-            StreamInfo::Sink(_) => None,
-            StreamInfo::SinkInput(info) => Some(info.index),
-        }
-    }
-
-    pub fn get_recent_peak(&mut self) -> anyhow::Result<Option<f32>> {
+    fn get_recent_peak(&mut self) -> anyhow::Result<Option<f32>> {
         let mut recent_peak: Option<f32> = None;
         'peek_loop: loop {
             match self.stream.peek()? {
@@ -614,64 +830,43 @@ impl Stream {
         Ok(recent_peak)
     }
 
-    pub fn set_volume(&mut self, pa: &mut PulseInterface, v: f32) {
-        let pa_volume = pulse::volume::Volume((pulse::volume::Volume::NORMAL.0 as f32 * v) as u32);
-        match &mut self.info {
-            StreamInfo::Sink(SinkInfo { index, volume, .. }) => {
-                volume.set(volume.len(), pa_volume);
+    fn apply_volume(&mut self, pa: &mut PulseInterface) {
+        let pa_volume = volume_to_pa(self.info.volume());
+        match self.info.id() {
+            StreamId::Sink(index) => {
                 pa.introspector
-                    .set_sink_volume_by_index(*index, volume, None);
+                    .set_sink_volume_by_index(index, &pa_volume, None);
             }
-            StreamInfo::SinkInput(SinkInputInfo { index, volume, .. }) => {
-                volume.set(volume.len(), pa_volume);
-                pa.introspector.set_sink_input_volume(*index, volume, None);
+            StreamId::SinkInput(index) => {
+                pa.introspector
+                    .set_sink_input_volume(index, &pa_volume, None);
             }
-        }
-    }
-
-    pub fn set_mute(&mut self, pa: &mut PulseInterface, new_mute: bool) {
-        match &mut self.info {
-            StreamInfo::Sink(SinkInfo { index, mute, .. }) => {
-                *mute = new_mute;
-                pa.introspector.set_sink_mute_by_index(*index, *mute, None);
+            StreamId::Source(index) => {
+                pa.introspector
+                    .set_source_volume_by_index(index, &pa_volume, None);
             }
-            StreamInfo::SinkInput(SinkInputInfo { index, mute, .. }) => {
-                *mute = new_mute;
-                pa.introspector.set_sink_input_mute(*index, *mute, None);
+            StreamId::SourceOutput(index) => {
+                pa.introspector
+                    .set_source_output_volume(index, &pa_volume, None);
             }
         }
     }
 
-    pub fn is_mute(&self) -> bool {
-        self.info.muted()
-    }
-
-    pub fn get_icon_name(&self, icon_mappings: &[config::IconMapping]) -> Option<String> {
-        if let StreamInfo::SinkInput(info) = &self.info {
-            'mappings_loop: for mapping in icon_mappings.iter() {
-                for (name, value) in mapping.property_matches.iter() {
-                    if info.properties.get_str(name).as_ref() != Some(value) {
-                        continue 'mappings_loop;
-                    }
-                }
-                return Some(mapping.icon.clone());
+    fn set_mute(&mut self, pa: &mut PulseInterface, mute: bool) {
+        self.info.set_muted(mute);
+        match self.info.id() {
+            StreamId::Sink(index) => {
+                pa.introspector.set_sink_mute_by_index(index, mute, None);
             }
-
-            if let Some(icon) = info
-                .properties
-                .get_str(pulse::proplist::properties::APPLICATION_ICON_NAME)
-            {
-                return Some(icon);
+            StreamId::SinkInput(index) => {
+                pa.introspector.set_sink_input_mute(index, mute, None);
+            }
+            StreamId::Source(index) => {
+                pa.introspector.set_source_mute_by_index(index, mute, None);
+            }
+            StreamId::SourceOutput(index) => {
+                pa.introspector.set_source_output_mute(index, mute, None);
             }
-        }
-        None
-    }
-
-    pub fn sink_name(&self) -> Option<String> {
-        if let StreamInfo::Sink(info) = &self.info {
-            info.name.clone()
-        } else {
-            None
         }
     }
 }
@@ -0,0 +1,825 @@
+//! Native PipeWire backend.
+//!
+//! PipeWire has no separate notion of sinks, sink-inputs, sources and source-outputs - they are
+//! all nodes, distinguished by their `media.class`.  This backend maps them onto the neutral
+//! types of [`backend`] so the mixer logic (and the property matching in the configuration)
+//! works the same as with PulseAudio.
+use crate::backend::{
+    self, SinkInfo, SinkInputInfo, SourceInfo, SourceOutputInfo, StreamId, StreamInfo,
+};
+use anyhow::Context;
+use pipewire::spa;
+use std::cell::Cell;
+use std::collections;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time;
+
+/// Events pushed by the PipeWire backend.
+pub type Event = backend::Event<Stream>;
+
+/// Node name of our own monitoring streams so we don't mistake them for recording applications.
+///
+/// Registry globals only carry a few well-known node properties, the name being one of them.
+const MONITOR_NODE_NAME: &str = "pavu-mixer-peak-detect";
+
+/// Placeholder for ids PipeWire does not have (`SPA_ID_INVALID`).
+const INVALID_ID: u32 = u32::MAX;
+
+/// How often peak data is reported to the application.
+const PEAK_INTERVAL: time::Duration = time::Duration::from_millis(40);
+
+/// The kind of object a node represents, derived from its `media.class`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    Sink,
+    SinkInput,
+    Source,
+    SourceOutput,
+}
+
+impl NodeKind {
+    fn from_media_class(media_class: &str) -> Option<Self> {
+        match media_class {
+            "Audio/Sink" | "Audio/Duplex" => Some(NodeKind::Sink),
+            "Stream/Output/Audio" => Some(NodeKind::SinkInput),
+            "Audio/Source" | "Audio/Source/Virtual" => Some(NodeKind::Source),
+            "Stream/Input/Audio" => Some(NodeKind::SourceOutput),
+            _ => None,
+        }
+    }
+}
+
+/// PipeWire volumes are linear while PulseAudio (and thus the rest of the daemon) uses a cubic
+/// scale.  Convert like `pipewire-pulse` does so faders behave the same with both backends.
+fn volume_from_pw(volume: &[f32]) -> Vec<f32> {
+    volume.iter().map(|v| v.cbrt()).collect()
+}
+
+fn volume_to_pw(volume: &[f32]) -> Vec<f32> {
+    volume.iter().map(|v| v * v * v).collect()
+}
+
+fn properties_from_pw(dict: &pipewire::spa::utils::dict::DictRef) -> backend::Properties {
+    let mut properties: backend::Properties = dict
+        .iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+
+    // PipeWire spells icon properties with a dash - provide the PulseAudio names as well so
+    // icon lookup works the same for both backends.
+    for (pw_key, pa_key) in [
+        ("application.icon-name", "application.icon_name"),
+        ("device.icon-name", "device.icon_name"),
+    ]
+    .iter()
+    {
+        if let Some(value) = properties.get(pw_key).cloned() {
+            properties.entry(pa_key.to_owned()).or_insert(value);
+        }
+    }
+    properties
+}
+
+/// Extract the sink name from the JSON value of the `default.audio.sink` metadata key, which
+/// looks like `{ "name": "alsa_output.pci-0000_00_1f.3.analog-stereo" }`.
+fn default_sink_name_from_metadata(value: &str) -> Option<String> {
+    let (_, rest) = value.split_once("\"name\"")?;
+    let (_, rest) = rest.split_once('"')?;
+    let (name, _) = rest.split_once('"')?;
+    Some(name.to_owned())
+}
+
+fn serialize_pod(value: &spa::pod::Value) -> anyhow::Result<Vec<u8>> {
+    Ok(
+        spa::pod::serialize::PodSerializer::serialize(std::io::Cursor::new(Vec::new()), value)
+            .map_err(|e| anyhow::anyhow!("failed serializing pod: {:?}", e))?
+            .0
+            .into_inner(),
+    )
+}
+
+fn props_pod(key: u32, value: spa::pod::Value) -> anyhow::Result<Vec<u8>> {
+    serialize_pod(&spa::pod::Value::Object(spa::pod::Object {
+        type_: spa::utils::SpaTypes::ObjectParamProps.as_raw(),
+        id: spa::param::ParamType::Props.as_raw(),
+        properties: vec![spa::pod::Property {
+            key,
+            flags: spa::pod::PropertyFlags::empty(),
+            value,
+        }],
+    }))
+}
+
+/// Parse volume and mute state out of a node's `Props` param.
+fn parse_props(pod: &spa::pod::Pod) -> Option<(Option<Vec<f32>>, Option<bool>)> {
+    let (_, value) =
+        spa::pod::deserialize::PodDeserializer::deserialize_any_from(pod.as_bytes()).ok()?;
+    let object = match value {
+        spa::pod::Value::Object(object) => object,
+        _ => return None,
+    };
+
+    let mut volume = None;
+    let mut mute = None;
+    for property in object.properties {
+        match (property.key, property.value) {
+            (
+                spa::sys::SPA_PROP_channelVolumes,
+                spa::pod::Value::ValueArray(spa::pod::ValueArray::Float(v)),
+            ) => volume = Some(volume_from_pw(&v)),
+            (spa::sys::SPA_PROP_mute, spa::pod::Value::Bool(m)) => mute = Some(m),
+            _ => (),
+        }
+    }
+    Some((volume, mute))
+}
+
+/// A bound audio node and everything we know about it.
+struct Node {
+    id: u32,
+    kind: NodeKind,
+    properties: backend::Properties,
+    proxy: pipewire::node::Node,
+    _listener: pipewire::node::NodeListener,
+    volume: Vec<f32>,
+    mute: bool,
+    /// Whether the application was already told about this node.  This only happens once its
+    /// `Props` (volume and mute state) are known.
+    announced: bool,
+}
+
+impl Node {
+    fn name(&self) -> Option<String> {
+        self.properties.get("node.name").cloned()
+    }
+
+    fn media_name(&self) -> Option<String> {
+        self.properties
+            .get("media.name")
+            .or_else(|| self.properties.get("node.description"))
+            .cloned()
+    }
+
+    fn sink_info(&self) -> SinkInfo {
+        SinkInfo {
+            index: self.id,
+            name: self.name(),
+            // the sink node is monitored directly
+            monitoring_source: self.id,
+            volume: self.volume.clone(),
+            mute: self.mute,
+        }
+    }
+
+    fn sink_input_info(&self) -> SinkInputInfo {
+        SinkInputInfo {
+            index: self.id,
+            name: self.media_name(),
+            application: self.properties.get("application.name").cloned(),
+            // the stream node is monitored directly, no need to know where it is routed
+            connected_sink: INVALID_ID,
+            properties: self.properties.clone(),
+            volume: self.volume.clone(),
+            mute: self.mute,
+        }
+    }
+
+    fn source_info(&self) -> SourceInfo {
+        SourceInfo {
+            index: self.id,
+            name: self.name(),
+            properties: self.properties.clone(),
+            volume: self.volume.clone(),
+            mute: self.mute,
+        }
+    }
+
+    fn source_output_info(&self) -> SourceOutputInfo {
+        SourceOutputInfo {
+            index: self.id,
+            name: self.media_name(),
+            application: self.properties.get("application.name").cloned(),
+            connected_source: INVALID_ID,
+            properties: self.properties.clone(),
+            volume: self.volume.clone(),
+            mute: self.mute,
+        }
+    }
+}
+
+enum InternalEvent {
+    /// A new audio node was bound and is waiting for its `Props`.
+    NodeAdded(Node),
+    /// A node reported new volume and/or mute state.
+    NodeProps {
+        id: u32,
+        volume: Option<Vec<f32>>,
+        mute: Option<bool>,
+    },
+    /// A global object was removed.  Might be one of our nodes.
+    GlobalRemoved(u32),
+    /// The `default` metadata object was bound - it needs to be kept alive and is used to change
+    /// the default sink.
+    MetadataBound(
+        pipewire::metadata::Metadata,
+        pipewire::metadata::MetadataListener,
+    ),
+    /// The default sink changed.
+    DefaultSinkName(String),
+}
+
+/// Interface for interacting with PipeWire directly.
+///
+/// Provides the same information as [`crate::pa::PulseInterface`], built on top of the PipeWire
+/// registry.  Peak data is computed from small capture streams attached to each monitored node.
+pub struct PipewireInterface {
+    mainloop: pipewire::main_loop::MainLoop,
+    _context: pipewire::context::Context,
+    core: pipewire::core::Core,
+    _registry: Rc<pipewire::registry::Registry>,
+    _registry_listener: pipewire::registry::Listener,
+    metadata: Option<(
+        pipewire::metadata::Metadata,
+        pipewire::metadata::MetadataListener,
+    )>,
+    external_rx: Option<mpsc::Receiver<Event>>,
+    external_tx: mpsc::Sender<Event>,
+    internal_rx: mpsc::Receiver<InternalEvent>,
+    internal_tx: mpsc::Sender<InternalEvent>,
+
+    nodes: collections::HashMap<u32, Node>,
+    /// Name of the default sink as reported by the session manager.
+    default_sink_name: Option<String>,
+    /// Node id of the default sink we last handed out a stream for.
+    current_default_sink: Option<u32>,
+}
+
+impl PipewireInterface {
+    pub fn init() -> anyhow::Result<Self> {
+        pipewire::init();
+
+        let mainloop =
+            pipewire::main_loop::MainLoop::new(None).context("failed creating mainloop")?;
+        let context =
+            pipewire::context::Context::new(&mainloop).context("failed creating context")?;
+        let core = context
+            .connect(Some(pipewire::properties::properties! {
+                *pipewire::keys::APP_NAME => "Pavu-Mixer Daemon",
+            }))
+            .context("failed connecting to PipeWire")?;
+        let registry = Rc::new(core.get_registry().context("failed getting registry")?);
+
+        let (external_tx, external_rx) = mpsc::channel();
+        let (internal_tx, internal_rx) = mpsc::channel();
+
+        let registry_listener = registry
+            .add_listener_local()
+            .global({
+                let registry = Rc::downgrade(&registry);
+                let internal_tx = internal_tx.clone();
+                move |global| {
+                    let registry = match registry.upgrade() {
+                        Some(registry) => registry,
+                        None => return,
+                    };
+                    let result = match global.type_ {
+                        pipewire::types::ObjectType::Node => {
+                            Self::bind_node(&registry, global, &internal_tx)
+                        }
+                        pipewire::types::ObjectType::Metadata => {
+                            Self::bind_metadata(&registry, global, &internal_tx)
+                        }
+                        _ => Ok(()),
+                    };
+                    if let Err(e) = result {
+                        log::warn!("failed binding PipeWire object {}: {:?}", global.id, e);
+                    }
+                }
+            })
+            .global_remove({
+                let internal_tx = internal_tx.clone();
+                move |id| {
+                    internal_tx
+                        .send(InternalEvent::GlobalRemoved(id))
+                        .expect("event channel error");
+                }
+            })
+            .register();
+
+        // Wait until the server processed everything up to here, so all existing objects were
+        // announced to the registry listener.
+        let done = Rc::new(Cell::new(false));
+        let pending = core.sync(0).context("failed syncing with PipeWire")?;
+        let _core_listener = core
+            .add_listener_local()
+            .done({
+                let done = done.clone();
+                move |id, seq| {
+                    if id == pipewire::core::PW_ID_CORE && seq == pending {
+                        done.set(true);
+                    }
+                }
+            })
+            .register();
+        while !done.get() {
+            Self::iterate_mainloop(&mainloop, true);
+        }
+
+        Ok(Self {
+            mainloop,
+            _context: context,
+            core,
+            _registry: registry,
+            _registry_listener: registry_listener,
+            metadata: None,
+            external_rx: Some(external_rx),
+            external_tx,
+            internal_rx,
+            internal_tx,
+            nodes: collections::HashMap::new(),
+            default_sink_name: None,
+            current_default_sink: None,
+        })
+    }
+
+    fn iterate_mainloop(mainloop: &pipewire::main_loop::MainLoop, block: bool) {
+        let timeout = if block {
+            // don't block forever - the mixer needs to be polled regularly
+            PEAK_INTERVAL
+        } else {
+            time::Duration::ZERO
+        };
+        mainloop.loop_().iterate(timeout);
+    }
+
+    fn bind_node(
+        registry: &pipewire::registry::Registry,
+        global: &pipewire::registry::GlobalObject<&spa::utils::dict::DictRef>,
+        internal_tx: &mpsc::Sender<InternalEvent>,
+    ) -> anyhow::Result<()> {
+        let props = match global.props {
+            Some(props) => props,
+            None => return Ok(()),
+        };
+        let kind = match props
+            .get("media.class")
+            .and_then(NodeKind::from_media_class)
+        {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+        if props.get("node.name") == Some(MONITOR_NODE_NAME) {
+            // one of our own peak detection streams
+            return Ok(());
+        }
+
+        let id = global.id;
+        let proxy: pipewire::node::Node = registry.bind(global).context("failed binding node")?;
+        let listener = proxy
+            .add_listener_local()
+            .param({
+                let internal_tx = internal_tx.clone();
+                move |_seq, param_type, _index, _next, param| {
+                    if param_type != spa::param::ParamType::Props {
+                        return;
+                    }
+                    if let Some((volume, mute)) = param.and_then(parse_props) {
+                        internal_tx
+                            .send(InternalEvent::NodeProps { id, volume, mute })
+                            .expect("event channel error");
+                    }
+                }
+            })
+            .register();
+        proxy.subscribe_params(&[spa::param::ParamType::Props]);
+
+        internal_tx
+            .send(InternalEvent::NodeAdded(Node {
+                id,
+                kind,
+                properties: properties_from_pw(props),
+                proxy,
+                _listener: listener,
+                volume: Vec::new(),
+                mute: false,
+                announced: false,
+            }))
+            .expect("event channel error");
+        Ok(())
+    }
+
+    fn bind_metadata(
+        registry: &pipewire::registry::Registry,
+        global: &pipewire::registry::GlobalObject<&spa::utils::dict::DictRef>,
+        internal_tx: &mpsc::Sender<InternalEvent>,
+    ) -> anyhow::Result<()> {
+        if global.props.and_then(|p| p.get("metadata.name")) != Some("default") {
+            return Ok(());
+        }
+
+        let metadata: pipewire::metadata::Metadata =
+            registry.bind(global).context("failed binding metadata")?;
+        let listener = metadata
+            .add_listener_local()
+            .property({
+                let internal_tx = internal_tx.clone();
+                move |_subject, key, _type, value| {
+                    if key == Some("default.audio.sink") {
+                        if let Some(name) = value.and_then(default_sink_name_from_metadata) {
+                            internal_tx
+                                .send(InternalEvent::DefaultSinkName(name))
+                                .expect("event channel error");
+                        }
+                    }
+                    0
+                }
+            })
+            .register();
+
+        internal_tx
+            .send(InternalEvent::MetadataBound(metadata, listener))
+            .expect("event channel error");
+        Ok(())
+    }
+
+    /// Hand out a new stream for the default sink if it changed.
+    fn update_default_sink(&mut self) {
+        let name = match &self.default_sink_name {
+            Some(name) => name,
+            None => return,
+        };
+        let info = self
+            .nodes
+            .values()
+            .find(|node| {
+                node.announced && node.kind == NodeKind::Sink && node.name().as_ref() == Some(name)
+            })
+            .map(Node::sink_info);
+        if let Some(info) = info {
+            if self.current_default_sink != Some(info.index) {
+                self.current_default_sink = Some(info.index);
+                let stream = Stream::new(self, StreamInfo::Sink(info));
+                self.external_tx
+                    .send(Event::NewDefaultSink(stream))
+                    .expect("event channel error");
+            }
+        }
+    }
+
+    fn handle_node_props(&mut self, id: u32, volume: Option<Vec<f32>>, mute: Option<bool>) {
+        let node = match self.nodes.get_mut(&id) {
+            Some(node) => node,
+            None => return,
+        };
+        if let Some(volume) = volume {
+            node.volume = volume;
+        }
+        if let Some(mute) = mute {
+            node.mute = mute;
+        }
+
+        let event = match (node.kind, node.announced) {
+            (NodeKind::Sink, false) => Event::SinkAdded(node.sink_info()),
+            (NodeKind::Sink, true) => Event::SinkChanged(node.sink_info()),
+            (NodeKind::SinkInput, false) => Event::SinkInputAdded(node.sink_input_info()),
+            (NodeKind::SinkInput, true) => Event::SinkInputChanged(node.sink_input_info()),
+            (NodeKind::Source, false) => Event::SourceAdded(node.source_info()),
+            (NodeKind::Source, true) => Event::SourceChanged(node.source_info()),
+            (NodeKind::SourceOutput, false) => Event::SourceOutputAdded(node.source_output_info()),
+            (NodeKind::SourceOutput, true) => Event::SourceOutputChanged(node.source_output_info()),
+        };
+        node.announced = true;
+        self.external_tx.send(event).expect("event channel error");
+    }
+
+    fn handle_global_removed(&mut self, id: u32) {
+        let node = match self.nodes.remove(&id) {
+            Some(node) => node,
+            None => return,
+        };
+        if !node.announced {
+            return;
+        }
+        let event = match node.kind {
+            NodeKind::Sink => {
+                if self.current_default_sink == Some(id) {
+                    self.current_default_sink = None;
+                }
+                Event::SinkRemoved(id)
+            }
+            NodeKind::SinkInput => Event::SinkInputRemoved(id),
+            NodeKind::Source => Event::SourceRemoved(id),
+            NodeKind::SourceOutput => Event::SourceOutputRemoved(id),
+        };
+        self.external_tx.send(event).expect("event channel error");
+    }
+
+    fn set_node_param(&self, id: u32, key: u32, value: spa::pod::Value) {
+        let node = match self.nodes.get(&id) {
+            Some(node) => node,
+            None => {
+                log::warn!("tried to control unknown PipeWire node {}", id);
+                return;
+            }
+        };
+        match props_pod(key, value) {
+            Ok(bytes) => {
+                let pod = spa::pod::Pod::from_bytes(&bytes).expect("serialized pod is invalid");
+                node.proxy.set_param(spa::param::ParamType::Props, 0, pod);
+            }
+            Err(e) => log::warn!("failed controlling PipeWire node {}: {:?}", id, e),
+        }
+    }
+}
+
+impl backend::Backend for PipewireInterface {
+    type Stream = Stream;
+
+    fn take_event_receiver(&mut self) -> Option<mpsc::Receiver<Event>> {
+        self.external_rx.take()
+    }
+
+    fn iterate(&mut self, block: bool) -> anyhow::Result<()> {
+        Self::iterate_mainloop(&self.mainloop, block);
+        while let Ok(event) = self.internal_rx.try_recv() {
+            match event {
+                InternalEvent::NodeAdded(node) => {
+                    self.nodes.insert(node.id, node);
+                }
+                InternalEvent::NodeProps { id, volume, mute } => {
+                    self.handle_node_props(id, volume, mute);
+                    // the default sink might only now be known
+                    self.update_default_sink();
+                }
+                InternalEvent::GlobalRemoved(id) => self.handle_global_removed(id),
+                InternalEvent::MetadataBound(metadata, listener) => {
+                    self.metadata = Some((metadata, listener));
+                }
+                InternalEvent::DefaultSinkName(name) => {
+                    self.default_sink_name = Some(name);
+                    self.update_default_sink();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rescan(&mut self) {
+        for node in self.nodes.values().filter(|node| node.announced) {
+            let event = match node.kind {
+                NodeKind::Sink => Event::SinkAdded(node.sink_info()),
+                NodeKind::SinkInput => Event::SinkInputAdded(node.sink_input_info()),
+                NodeKind::Source => Event::SourceAdded(node.source_info()),
+                NodeKind::SourceOutput => Event::SourceOutputAdded(node.source_output_info()),
+            };
+            self.external_tx.send(event).expect("event channel error");
+        }
+    }
+
+    /// Create a stream for a sink-input.  The stream node is monitored directly so the stream is
+    /// pushed as an [`Event::NewSinkInput`] right away.
+    fn request_sink_input_stream(&mut self, info: SinkInputInfo, for_channel: common::Channel) {
+        let stream = Stream::new(self, StreamInfo::SinkInput(info));
+        self.external_tx
+            .send(Event::NewSinkInput(for_channel, stream))
+            .expect("event channel error");
+    }
+
+    fn request_sink_stream(
+        &mut self,
+        info: SinkInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = Stream::new(self, StreamInfo::Sink(info));
+        self.external_tx
+            .send(Event::NewSink(for_channel, stream))
+            .expect("event channel error");
+        Ok(())
+    }
+
+    fn request_source_stream(
+        &mut self,
+        info: SourceInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = Stream::new(self, StreamInfo::Source(info));
+        self.external_tx
+            .send(Event::NewSource(for_channel, stream))
+            .expect("event channel error");
+        Ok(())
+    }
+
+    fn request_source_output_stream(
+        &mut self,
+        info: SourceOutputInfo,
+        for_channel: common::Channel,
+    ) -> anyhow::Result<()> {
+        let stream = Stream::new(self, StreamInfo::SourceOutput(info));
+        self.external_tx
+            .send(Event::NewSourceOutput(for_channel, stream))
+            .expect("event channel error");
+        Ok(())
+    }
+    fn set_default_sink(&mut self, name: &str) {
+        match &self.metadata {
+            Some((metadata, _)) => metadata.set_property(
+                0,
+                "default.configured.audio.sink",
+                Some("Spa:String:JSON"),
+                Some(&format!("{{ \"name\": \"{}\" }}", name)),
+            ),
+            None => log::warn!("cannot switch the default sink before the metadata is bound"),
+        }
+    }
+}
+
+/// Peak data collected by the capture stream's process callback.
+#[derive(Default)]
+struct PeakState {
+    peak: Option<f32>,
+    last_report: Option<time::Instant>,
+}
+
+pub struct Stream {
+    info: StreamInfo,
+    core: pipewire::core::Core,
+    external_tx: mpsc::Sender<Event>,
+    connected_channel: Rc<Cell<Option<(common::Channel, usize)>>>,
+    peak_state: Rc<std::cell::RefCell<PeakState>>,
+    capture: Option<(
+        pipewire::stream::Stream,
+        pipewire::stream::StreamListener<()>,
+    )>,
+}
+
+impl std::fmt::Debug for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stream").field("info", &self.info).finish()
+    }
+}
+
+impl Stream {
+    fn new(pw: &PipewireInterface, info: StreamInfo) -> Self {
+        Self {
+            info,
+            core: pw.core.clone(),
+            external_tx: pw.external_tx.clone(),
+            connected_channel: Rc::new(Cell::new(None)),
+            peak_state: Default::default(),
+            capture: None,
+        }
+    }
+
+    fn node_id(&self) -> u32 {
+        match self.info.id() {
+            StreamId::Sink(id)
+            | StreamId::SinkInput(id)
+            | StreamId::Source(id)
+            | StreamId::SourceOutput(id) => id,
+        }
+    }
+}
+
+impl backend::Stream for Stream {
+    type Backend = PipewireInterface;
+
+    fn info(&self) -> &StreamInfo {
+        &self.info
+    }
+
+    fn info_mut(&mut self) -> &mut StreamInfo {
+        &mut self.info
+    }
+
+    fn connect(&mut self) -> anyhow::Result<()> {
+        let mut props = pipewire::properties::properties! {
+            *pipewire::keys::MEDIA_TYPE => "Audio",
+            *pipewire::keys::MEDIA_CATEGORY => "Capture",
+            *pipewire::keys::MEDIA_ROLE => "DSP",
+            *pipewire::keys::NODE_NAME => MONITOR_NODE_NAME,
+            *pipewire::keys::TARGET_OBJECT => self.node_id().to_string(),
+            "node.dont-reconnect" => "true",
+            "node.passive" => "true",
+        };
+        match self.info {
+            StreamInfo::Sink(_) => props.insert(*pipewire::keys::STREAM_CAPTURE_SINK, "true"),
+            StreamInfo::SinkInput(_) | StreamInfo::SourceOutput(_) => {
+                props.insert(*pipewire::keys::STREAM_MONITOR, "true")
+            }
+            StreamInfo::Source(_) => (),
+        }
+
+        let stream = pipewire::stream::Stream::new(
+            &self.core,
+            &format!("Peak Detect for {}", self.info.description()),
+            props,
+        )
+        .context("failed creating monitoring stream")?;
+
+        let listener = stream
+            .add_local_listener_with_user_data(())
+            .process({
+                let external_tx = self.external_tx.clone();
+                let connected_channel = self.connected_channel.clone();
+                let peak_state = self.peak_state.clone();
+                move |stream, _| {
+                    let mut buffer = match stream.dequeue_buffer() {
+                        Some(buffer) => buffer,
+                        None => return,
+                    };
+                    let data = match buffer.datas_mut().first_mut() {
+                        Some(data) => data,
+                        None => return,
+                    };
+                    let size = data.chunk().size() as usize;
+                    let samples = match data.data() {
+                        Some(samples) => &samples[..size.min(samples.len())],
+                        None => return,
+                    };
+                    let peak = samples
+                        .chunks_exact(std::mem::size_of::<f32>())
+                        .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]).abs())
+                        .fold(0.0f32, f32::max);
+
+                    let mut peak_state = peak_state.borrow_mut();
+                    let p = peak_state.peak.get_or_insert(0.0);
+                    *p = p.max(peak);
+
+                    // PipeWire has no peak detection of its own - rate limit the updates so we
+                    // don't flood the mixer.
+                    let now = time::Instant::now();
+                    if peak_state
+                        .last_report
+                        .map(|last| now - last >= PEAK_INTERVAL)
+                        .unwrap_or(true)
+                    {
+                        peak_state.last_report = Some(now);
+                        if let Some((ch, index)) = connected_channel.get() {
+                            external_tx
+                                .send(Event::NewPeakData(ch, index))
+                                .expect("event channel error");
+                        }
+                    }
+                }
+            })
+            .register()
+            .context("failed registering stream listener")?;
+
+        let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+        audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
+        audio_info.set_channels(1);
+        let format = serialize_pod(&spa::pod::Value::Object(spa::pod::Object {
+            type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+            id: spa::param::ParamType::EnumFormat.as_raw(),
+            properties: audio_info.into(),
+        }))?;
+        let mut params = [spa::pod::Pod::from_bytes(&format).expect("serialized pod is invalid")];
+
+        stream
+            .connect(
+                spa::utils::Direction::Input,
+                None,
+                pipewire::stream::StreamFlags::AUTOCONNECT
+                    | pipewire::stream::StreamFlags::MAP_BUFFERS,
+                &mut params,
+            )
+            .context("failed connecting monitoring stream")?;
+
+        self.capture = Some((stream, listener));
+        Ok(())
+    }
+
+    fn set_connected_channel(&self, ch: common::Channel, index: usize) {
+        self.connected_channel.set(Some((ch, index)));
+    }
+
+    fn get_recent_peak(&mut self) -> anyhow::Result<Option<f32>> {
+        Ok(self.peak_state.borrow_mut().peak.take())
+    }
+
+    fn apply_volume(&mut self, pw: &mut PipewireInterface) {
+        let value = spa::pod::Value::ValueArray(spa::pod::ValueArray::Float(volume_to_pw(
+            self.info.volume(),
+        )));
+        pw.set_node_param(self.node_id(), spa::sys::SPA_PROP_channelVolumes, value);
+    }
+
+    fn set_mute(&mut self, pw: &mut PipewireInterface, mute: bool) {
+        self.info.set_muted(mute);
+        pw.set_node_param(
+            self.node_id(),
+            spa::sys::SPA_PROP_mute,
+            spa::pod::Value::Bool(mute),
+        );
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if let Some((stream, _listener)) = self.capture.take() {
+            // explicitly ignore disconnection errors - we don't care!
+            let _ = stream.disconnect();
+        }
+    }
+}
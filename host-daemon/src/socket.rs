@@ -0,0 +1,17 @@
+//! Location of the control socket, shared by the daemon and `pavu-mixer-ctl`.
+use anyhow::Context;
+use std::os::unix::fs::MetadataExt;
+use std::path;
+
+/// The socket lives in the user's runtime directory.  Without one, it goes into the shared
+/// temporary directory under a name of its own for every user.
+pub fn socket_path() -> anyhow::Result<path::PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(path::PathBuf::from(dir).join("pavu-mixer.sock"));
+    }
+    // `/proc/self` belongs to the user running this process
+    let uid = std::fs::metadata("/proc/self")
+        .context("XDG_RUNTIME_DIR is not set and the user id is unknown")?
+        .uid();
+    Ok(std::env::temp_dir().join(format!("pavu-mixer-{}.sock", uid)))
+}
@@ -0,0 +1,144 @@
+//! State the daemon keeps across restarts, as opposed to the user-edited configuration.
+use crate::backend;
+use anyhow::Context;
+use std::path;
+
+/// Properties which make up the identity of an application, to recognize it again later.
+const IDENTITY_PROPERTIES: &[&str] = &[
+    "application.process.binary",
+    "application.id",
+    "application.name",
+];
+
+fn file_path() -> anyhow::Result<path::PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => path::PathBuf::from(dir),
+        None => path::PathBuf::from(
+            std::env::var_os("HOME").context("neither XDG_STATE_HOME nor HOME are set")?,
+        )
+        .join(".local/state"),
+    };
+    Ok(state_home.join("pavu-mixer").join("state.toml"))
+}
+
+/// The identity of the application a stream belongs to.
+///
+/// Returns `None` if the stream does not report any of the relevant properties.
+pub fn identity(properties: &backend::Properties) -> Option<backend::Properties> {
+    let identity: backend::Properties = IDENTITY_PROPERTIES
+        .iter()
+        .filter_map(|name| Some((name.to_string(), properties.get(*name)?.clone())))
+        .collect();
+    if identity.is_empty() {
+        None
+    } else {
+        Some(identity)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct State {
+    #[serde(default)]
+    pub pins: Vec<Pin>,
+}
+
+/// An application pinned to a channel by hand.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Pin {
+    pub profile: String,
+    /// Channel number, starting at 1.
+    pub channel: usize,
+    pub identity: backend::Properties,
+}
+
+impl State {
+    /// Load the state file, starting out empty if there is none yet.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = file_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let source =
+            std::fs::read_to_string(&path).with_context(|| format!("failed reading {:?}", path))?;
+        toml::from_str(&source).with_context(|| format!("failed parsing {:?}", path))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = file_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed creating directory {:?}", dir))?;
+        }
+        std::fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("failed writing {:?}", path))
+    }
+
+    /// Index of the channel a stream with these properties is pinned to in the given profile.
+    pub fn pinned_channel(&self, profile: &str, properties: &backend::Properties) -> Option<usize> {
+        let identity = identity(properties)?;
+        self.pins
+            .iter()
+            .find(|pin| pin.profile == profile && pin.identity == identity)
+            .and_then(|pin| pin.channel.checked_sub(1))
+    }
+
+    /// Pin an application to the channel with the given index, replacing a previous pin.
+    pub fn pin(&mut self, profile: &str, identity: backend::Properties, index: usize) {
+        self.unpin(profile, &identity);
+        self.pins.push(Pin {
+            profile: profile.to_owned(),
+            channel: index + 1,
+            identity,
+        });
+    }
+
+    /// Returns whether the application was pinned.
+    pub fn unpin(&mut self, profile: &str, identity: &backend::Properties) -> bool {
+        let count = self.pins.len();
+        self.pins
+            .retain(|pin| pin.profile != profile || pin.identity != *identity);
+        self.pins.len() != count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(pairs: &[(&str, &str)]) -> backend::Properties {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn pins_match_by_identity_and_profile() {
+        let game = properties(&[
+            ("application.process.binary", "wine64-preloader"),
+            ("application.name", "Game.exe"),
+            ("media.name", "audio stream #1"),
+        ]);
+        let mut state = State::default();
+        state.pin("default", identity(&game).unwrap(), 2);
+
+        // other stream of the same application
+        let mut other_stream = game.clone();
+        other_stream.insert("media.name".to_owned(), "audio stream #2".to_owned());
+        assert_eq!(state.pinned_channel("default", &other_stream), Some(2));
+        assert_eq!(state.pinned_channel("gaming", &game), None);
+
+        // other application under the same binary
+        let mut other_game = game.clone();
+        other_game.insert("application.name".to_owned(), "Other.exe".to_owned());
+        assert_eq!(state.pinned_channel("default", &other_game), None);
+
+        assert!(identity(&properties(&[("media.name", "x")])).is_none());
+
+        // survives saving and loading
+        let state: State = toml::from_str(&toml::to_string(&state).unwrap()).unwrap();
+        assert_eq!(state.pinned_channel("default", &game), Some(2));
+    }
+}
@@ -0,0 +1,242 @@
+//! Interactive `--generate-config` mode, writing a configuration for the streams playing right now.
+use crate::backend;
+use crate::config;
+use crate::state;
+use anyhow::Context;
+use std::collections;
+use std::io::{self, BufRead, Write};
+use std::time;
+
+/// How long to wait for the audio server to report all sink-inputs.
+const SCAN_TIME: time::Duration = time::Duration::from_secs(1);
+
+/// An application the user put on a channel.
+#[derive(Debug, Clone, PartialEq)]
+struct Pick {
+    /// Channel index, starting at 0.
+    channel: usize,
+    /// The properties identifying the stream's application.
+    identity: backend::Properties,
+    icon: Option<String>,
+}
+
+fn collect_sink_inputs<B: backend::Backend>(
+    mut backend: B,
+) -> anyhow::Result<Vec<backend::SinkInputInfo>> {
+    let events = backend
+        .take_event_receiver()
+        .expect("events channel missing");
+    let mut sink_inputs = collections::BTreeMap::new();
+    let start = time::Instant::now();
+    while start.elapsed() < SCAN_TIME {
+        backend.iterate(false)?;
+        for event in events.try_iter() {
+            match event {
+                backend::Event::SinkInputAdded(info) | backend::Event::SinkInputChanged(info) => {
+                    sink_inputs.insert(info.index, info);
+                }
+                backend::Event::SinkInputRemoved(index) => {
+                    sink_inputs.remove(&index);
+                }
+                _ => (),
+            }
+        }
+        std::thread::sleep(time::Duration::from_millis(10));
+    }
+    Ok(sink_inputs.into_values().collect())
+}
+
+/// Ask a question, returning the trimmed answer or the default for an empty one.
+fn prompt(input: &mut impl BufRead, question: &str, default: &str) -> anyhow::Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        anyhow::bail!("aborted");
+    }
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_owned())
+}
+
+pub fn run<B: backend::Backend>(backend: B) -> anyhow::Result<()> {
+    let path = config::file_path()?;
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    println!("Looking for playing streams...");
+    let sink_inputs = collect_sink_inputs(backend)?;
+    if sink_inputs.is_empty() {
+        anyhow::bail!("no streams are playing, start the applications you want to assign first");
+    }
+
+    let channel_count = loop {
+        match prompt(&mut input, "How many channels does the mixer have?", "4")?.parse() {
+            Ok(count) if count > 0 => break count,
+            _ => println!("Please enter a number greater than 0."),
+        }
+    };
+
+    let mut picks = Vec::new();
+    for info in sink_inputs.iter() {
+        println!();
+        println!(
+            "{} of {}",
+            info.name.as_deref().unwrap_or("Unnamed stream"),
+            info.application
+                .as_deref()
+                .unwrap_or("an unknown application"),
+        );
+        for (name, value) in info.properties.iter() {
+            println!("    {} = {:?}", name, value);
+        }
+        let identity = match state::identity(&info.properties) {
+            Some(identity) => identity,
+            None => {
+                println!("This stream does not report any application properties, skipping.");
+                continue;
+            }
+        };
+        if let Some(other) = picks.iter().find(|pick: &&Pick| pick.identity == identity) {
+            println!(
+                "Streams of this application already go to channel {}.",
+                other.channel + 1
+            );
+            continue;
+        }
+
+        let channel = loop {
+            let question = format!("Channel (1-{}, empty to skip)", channel_count);
+            let answer = prompt(&mut input, &question, "")?;
+            if answer.is_empty() {
+                break None;
+            }
+            match answer.parse::<usize>() {
+                Ok(number) if (1..=channel_count).contains(&number) => break Some(number - 1),
+                _ => println!("Please enter a channel between 1 and {}.", channel_count),
+            }
+        };
+        let channel = match channel {
+            Some(channel) => channel,
+            None => continue,
+        };
+
+        let suggestion = info
+            .properties
+            .get("application.icon_name")
+            .map(String::as_str)
+            .unwrap_or("");
+        let icon = prompt(&mut input, "Icon (empty for none)", suggestion)?;
+        picks.push(Pick {
+            channel,
+            identity,
+            icon: Some(icon).filter(|icon| !icon.is_empty()),
+        });
+    }
+
+    let source = generate(channel_count, &picks)?;
+    println!();
+    if path.exists() {
+        let answer = prompt(&mut input, &format!("Overwrite {:?}? (y/n)", path), "n")?;
+        if answer != "y" {
+            println!("{}", source);
+            println!("Configuration was not written.");
+            return Ok(());
+        }
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed creating directory {:?}", dir))?;
+    }
+    std::fs::write(&path, source).with_context(|| format!("failed writing {:?}", path))?;
+    println!("Configuration written to {:?}.", path);
+    Ok(())
+}
+
+/// Build a configuration from the default one, with its channels and icon mappings replaced.
+fn generate(channel_count: usize, picks: &[Pick]) -> anyhow::Result<String> {
+    let mut document: toml_edit::DocumentMut = include_str!("default-config.toml").parse()?;
+    // keep the explanation in front of the example channels
+    let prefix = document
+        .get("channels")
+        .and_then(|channels| channels.as_array_of_tables()?.get(0))
+        .and_then(|channel| channel.decor().prefix()?.as_str())
+        .unwrap_or("")
+        .to_owned();
+    document.remove("channels");
+    document.remove("icon-mappings");
+
+    let mut channels = toml_edit::ArrayOfTables::new();
+    for index in 0..channel_count {
+        let mut channel = toml_edit::Table::new();
+        if index == 0 {
+            channel.decor_mut().set_prefix(prefix.clone());
+        }
+        let mut sets = toml_edit::ArrayOfTables::new();
+        for pick in picks.iter().filter(|pick| pick.channel == index) {
+            sets.push(config::exact_match_set(&pick.identity).into_table());
+        }
+        if !sets.is_empty() {
+            channel.insert("property-matches", toml_edit::Item::ArrayOfTables(sets));
+        }
+        channels.push(channel);
+    }
+    document.insert("channels", toml_edit::Item::ArrayOfTables(channels));
+
+    let mut icon_mappings = toml_edit::ArrayOfTables::new();
+    for pick in picks.iter() {
+        if let Some(icon) = &pick.icon {
+            let mut mapping = toml_edit::Table::new();
+            mapping.insert("icon", toml_edit::value(icon.as_str()));
+            mapping.insert(
+                "property-matches",
+                toml_edit::Item::Table(config::exact_match_set(&pick.identity).into_table()),
+            );
+            icon_mappings.push(mapping);
+        }
+    }
+    document.insert(
+        "icon-mappings",
+        toml_edit::Item::ArrayOfTables(icon_mappings),
+    );
+
+    let source = document.to_string();
+    config::parse(&source).context("generated configuration is invalid")?;
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_config_matches_picked_streams() {
+        let properties = |name: &str| -> backend::Properties {
+            std::iter::once(("application.name".to_owned(), name.to_owned())).collect()
+        };
+        let picks = [
+            Pick {
+                channel: 1,
+                identity: properties("Firefox"),
+                icon: Some("firefox".to_owned()),
+            },
+            Pick {
+                channel: 1,
+                identity: properties("Chromium"),
+                icon: None,
+            },
+        ];
+        let source = generate(3, &picks).unwrap();
+        let config = config::parse(&source).unwrap();
+
+        assert!(source.contains("# One entry per fader"));
+        assert_eq!(config.channels.len(), 3);
+        assert!(config.channels[0].property_matches.is_empty());
+        assert_eq!(config.channels[1].property_matches.len(), 2);
+        assert_eq!(config.icon_mappings.len(), 1);
+        assert_eq!(config.icon_mappings[0].icon, "firefox");
+    }
+}